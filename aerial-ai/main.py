@@ -44,7 +44,7 @@ def handle_completion(
     for tool_call in message.tool_calls:
         # TODO: Add logger for debugging
         tool_response = handle_function(
-            tool_call.function.name, json.loads(tool_call.function.arguments), tools
+            tool_call.function.name, tool_call.function.arguments
         )
         messages.append(
             {
@@ -58,27 +58,21 @@ def handle_completion(
     return handle_completion(completion, client, messages, tools)
 
 
-def parse_arguments(arguments: dict, tools: list[dict], func_name: str) -> list[str]:
-    tool = next((tool for tool in tools if tool["function"]["name"] == func_name))
-    pos_args = []
-    other_args = []
-    for name, data in tool["function"]["parameters"]["properties"].items():
-        if "index" in data:
-            pos_args.insert(data["index"], arguments[name])
-        elif name in arguments:
-            other_args.append(f"--{name}")
-            other_args.append(str(arguments[name]))
-    print(f"{func_name} {pos_args + other_args}")
-    return pos_args + other_args
-
-
-def handle_function(name: str, arguments: dict, tools: list[dict]):
-    commands = (
-        ["./aerial-utils"] + name.split("_") + parse_arguments(arguments, tools, name)
+def handle_function(name: str, arguments: str):
+    # exec-tool checks the arguments and maps them onto the command, the result or error is JSON on stdout
+    call = json.dumps({"name": name, "arguments": arguments})
+    print(f"{name} {arguments}")
+    process = subprocess.run(
+        ["./aerial-utils", "exec-tool", "-"],
+        input=call,
+        capture_output=True,
+        text=True,
+        cwd=".",
     )
-    process = subprocess.run(commands, capture_output=True, text=True, cwd=".")
-    # TODO: This is a weird approach because the aerial-utils error is in stderr and not stdout
-    return process.stdout + process.stderr
+    # Anything that fails before the call is made, like invalid arguments to exec-tool itself, is only on stderr
+    if process.returncode != 0 or not process.stdout.strip():
+        return json.dumps({"error": {"kind": "exec_tool_failed", "message": process.stderr.strip()}})
+    return process.stdout
 
 
 def main():
@@ -0,0 +1,188 @@
+//! The `aerial-utils` command line interface, every module is a subcommand of it
+
+use crate::mcp::McpServer;
+use crate::modules::{plugin::PluginError, print_subcommand_specs, ModuleRegistry, SpecFormat};
+use crate::tool_call::exec_tool_in;
+use crate::utils::{
+    cache::{Cache, CacheError},
+    config::ConfigError,
+    output::OutputFormat,
+    paths::{ResolvedPath, CACHE_FILE, CONFIG_FILE},
+    Config,
+};
+use clap::{ArgMatches, Command, CommandFactory, FromArgMatches, Parser, Subcommand};
+use serde::Serialize;
+use std::{error::Error, ffi::OsString, fmt::Display, io, path::PathBuf};
+use thiserror::Error;
+
+#[derive(Parser)]
+#[command(version, subcommand_required = true)]
+pub struct AerialUtilsArgs {
+    #[command(subcommand)]
+    pub builtin: Option<Builtin>,
+    /// The format command results and errors are printed in
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub output: OutputFormat,
+    /// The config file, instead of the one found from `AERIAL_CONFIG`, `$XDG_CONFIG_HOME/aerial` or the working directory
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// The cache file, instead of the one found from `AERIAL_CACHE`, `$XDG_CACHE_HOME/aerial` or the working directory
+    #[arg(long, global = true, value_name = "PATH")]
+    pub cache: Option<PathBuf>,
+    /// The module subcommand and its matches, parsed by the module itself once it's known to be enabled
+    #[arg(skip)]
+    pub module: Option<(String, ArgMatches)>,
+}
+
+impl AerialUtilsArgs {
+    /// The CLI, with the registered modules as subcommands
+    pub fn command_with(registry: &ModuleRegistry) -> clap::Command {
+        registry.modules().fold(Self::command(), |cmd, module| cmd.subcommand(module.command()))
+    }
+
+    pub fn parse_with(registry: &ModuleRegistry) -> Self {
+        Self::try_parse_with(registry, std::env::args_os()).unwrap_or_else(|err| err.exit())
+    }
+
+    pub fn try_parse_with<I: IntoIterator<Item = T>, T: Into<OsString> + Clone>(registry: &ModuleRegistry, argv: I) -> Result<Self, clap::Error> {
+        let matches = Self::command_with(registry).try_get_matches_from(argv)?;
+        let mut args = Self::from_arg_matches(&matches)?;
+        if args.builtin.is_none() {
+            args.module = matches.subcommand().map(|(name, matches)| (name.to_string(), matches.clone()));
+        }
+        Ok(args)
+    }
+}
+
+/// The config and cache files a command uses
+#[derive(Serialize)]
+pub struct Paths {
+    pub config: ResolvedPath,
+    pub cache: ResolvedPath,
+}
+
+impl Paths {
+    pub fn resolve(args: &AerialUtilsArgs) -> Self {
+        Self {
+            config: CONFIG_FILE.resolve(args.config.clone()),
+            cache: CACHE_FILE.resolve(args.cache.clone()),
+        }
+    }
+}
+
+impl Display for Paths {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Config: {}\nCache: {}", self.config, self.cache)
+    }
+}
+
+// The commands of the CLI itself, the others come from the registered modules
+#[derive(Subcommand)]
+pub enum Builtin {
+    /// Print the commands of the enabled modules as tool specifications for LLM assistants
+    CommandSpecs {
+        /// The API the specifications are for
+        #[arg(long, value_enum, default_value_t)]
+        format: SpecFormat,
+    },
+    /// Print which config and cache files are used, and how they were found
+    Paths,
+    /// Serve the commands of the enabled modules as the tools of an MCP (Model Context Protocol) server on stdin and stdout
+    Mcp,
+    /// Run a tool call of an LLM assistant and print its result as JSON. The call is a tool from `command-specs` with its
+    /// arguments, like `{"name": "music_search", "arguments": {"query": "..."}}`
+    ExecTool {
+        /// The call as JSON, read from stdin when it's left out or `-`
+        call: Option<String>,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum AppError {
+    #[error("Cache error: {0}")]
+    CacheError(CacheError),
+    #[error("Config error: {0}")]
+    ConfigError(ConfigError),
+    #[error("No `[modules.{0}]` section found in the config file")]
+    MissingConfig(&'static str),
+    #[error("The `{0}` module is turned off in the config")]
+    ModuleDisabled(String),
+    #[error("{0} module error: {2}")]
+    ModuleError(String, &'static str, Box<dyn Error>),
+    #[error("Plugin error: {0}")]
+    PluginError(PluginError),
+    #[error("{0}")]
+    InvalidArguments(clap::Error),
+    #[error("MCP server error: {0}")]
+    McpError(io::Error),
+}
+
+impl AppError {
+    /// A stable name for the error in the serialized output formats
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::CacheError(_) => "cache_error",
+            AppError::ConfigError(_) => "config_error",
+            AppError::MissingConfig(_) => "missing_config",
+            AppError::ModuleDisabled(_) => "module_disabled",
+            AppError::ModuleError(_, kind, _) => kind,
+            AppError::PluginError(err) => err.kind(),
+            AppError::InvalidArguments(_) => "invalid_arguments",
+            AppError::McpError(_) => "mcp_error",
+        }
+    }
+}
+
+pub fn run_module(args: AerialUtilsArgs, registry: &ModuleRegistry, paths: &Paths) -> Result<(), AppError> {
+    let output = args.output;
+    // Listing the paths doesn't read the files, so it still works when one of them is invalid
+    if let Some(Builtin::Paths) = args.builtin {
+        output.print(paths);
+        return Ok(());
+    }
+    // Failed calls are results as well, the caller gets them in the same place even when the files can't be read.
+    // The runner reads and writes the cache for each call itself
+    if let Some(Builtin::ExecTool { call }) = args.builtin {
+        OutputFormat::Json.print(&exec_tool_in(registry, paths, call.as_deref()));
+        return Ok(());
+    }
+    let mut cache = Cache::from_file(&paths.cache.path).map_err(AppError::CacheError)?;
+    let config = Config::from_file(&paths.config.path).map_err(AppError::ConfigError)?;
+    let res = match (args.builtin, args.module) {
+        (Some(Builtin::CommandSpecs { format }), _) => {
+            print_subcommand_specs(tools_command(registry, &config), format);
+            Ok(())
+        }
+        (Some(Builtin::Mcp), _) => McpServer::new(registry, paths, config).serve().map_err(AppError::McpError),
+        (_, Some((name, matches))) => match registry.get(&name) {
+            Some(module) if config.modules.is_enabled(&module.config_section) => module.run(&matches, &config, &mut cache, paths, output),
+            _ => Err(AppError::ModuleDisabled(name)),
+        },
+        // Parsing requires a subcommand
+        (Some(Builtin::Paths | Builtin::ExecTool { .. }), _) | (None, None) => unreachable!(),
+    };
+    // NOTE: Cache won't be changed if the operation failed, might be good because
+    // running the same command twice shouldn't get a different result
+    cache.to_file(&paths.cache.path).map_err(AppError::CacheError)?;
+    res
+}
+
+/// The command the tools are made of, with only the commands of the enabled modules since the CLI's own commands (like
+/// `paths` or `mcp`) can't be called as tools
+pub fn tools_command(registry: &ModuleRegistry, config: &Config) -> Command {
+    Command::new("aerial-utils").subcommands(spec_commands(registry, config))
+}
+
+/// The commands of the enabled modules as they're described in the specs
+pub fn spec_commands(registry: &ModuleRegistry, config: &Config) -> Vec<Command> {
+    // A broken plugin shouldn't hide the commands of the other modules
+    registry
+        .enabled(config)
+        .filter_map(|module| {
+            module
+                .spec_command()
+                .inspect_err(|err| eprintln!("WARNING: Leaving the `{}` module out of the specs: {}", module.name, err))
+                .ok()
+        })
+        .collect()
+}
@@ -0,0 +1,35 @@
+//! The library behind the `aerial-utils` CLI, Aerial's access to online services and local functionality.
+//!
+//! - [`utils`] has the building blocks for web APIs: describing requests with [`utils::ApiRequestSpec`] and
+//!   [`impl_endpoint!`], sending them with retries and paging through [`utils::api_handler::ApiHandler`], OAuth and the
+//!   config and cache files.
+//! - [`modules`] has the functionality itself, like controlling music through [`modules::music::MusicClient`].
+//! - [`cli`] is the command line interface, each module is a subcommand of it through the `modules::ModuleRegistry`.
+//! - [`mcp`] serves the modules' commands as tools to MCP clients, like LLM assistants.
+//! - [`tool_call`] runs the tool calls of LLM assistants, for `exec-tool` and the MCP server.
+//!
+//! Everything but the CLI is usable without the default `cli` feature, which is what depends on clap.
+//!
+//! ```no_run
+//! use aerial_utils::modules::music::{MusicClient, PageArgs, SpotifyClient};
+//! use aerial_utils::utils::{cache::SpotifyCache, config::SpotifyConfig, paths, Cache, Config};
+//!
+//! let config = Config::from_file(&paths::CONFIG_FILE.resolve(None).path)?;
+//! let cache = Cache::from_file(&paths::CACHE_FILE.resolve(None).path)?;
+//! let spotify_config: SpotifyConfig = config.modules.section()?.expect("Spotify isn't configured");
+//! let spotify_cache: SpotifyCache = cache.modules.section()?;
+//! let spotify = SpotifyClient::new(&spotify_config, &spotify_cache, spotify_config.http.build_client()?)?;
+//! for track in spotify.get_top_tracks(Default::default(), PageArgs::default())? {
+//!     println!("{}\n", track);
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "cli")]
+pub mod mcp;
+pub mod modules;
+#[cfg(feature = "cli")]
+pub mod tool_call;
+pub mod utils;
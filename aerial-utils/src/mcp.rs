@@ -0,0 +1,151 @@
+//! `aerial-utils mcp`, a Model Context Protocol server with the commands of the enabled modules as its tools, so any MCP
+//! client can use them. Messages are JSON-RPC, one per line on stdin and stdout, and the tools are run in the same
+//! process by a [`ToolRunner`]. Results are JSON, like with `--output json`
+
+use crate::{
+    cli::Paths,
+    modules::ModuleRegistry,
+    tool_call::{ToolError, ToolRunner},
+    utils::{
+        output::{ErrorOutput, OutputFormat},
+        Config,
+    },
+};
+use serde_json::{json, Map, Value};
+use std::io::{self, BufRead, Write};
+use thiserror::Error;
+
+/// The protocol versions the server can speak, the latest first
+pub const PROTOCOL_VERSIONS: [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+
+#[derive(Error, Debug)]
+pub enum RpcError {
+    #[error("Invalid JSON: {0}")]
+    InvalidJson(serde_json::Error),
+    #[error("Not a JSON-RPC request")]
+    InvalidRequest,
+    #[error("Unknown method `{0}`")]
+    UnknownMethod(String),
+    #[error("Invalid params: {0}")]
+    InvalidParams(&'static str),
+    /// An unknown tool or invalid arguments, a command that ran and failed is a result
+    #[error("{0}")]
+    InvalidCall(ToolError),
+}
+
+impl RpcError {
+    /// The JSON-RPC error code
+    pub fn code(&self) -> i64 {
+        match self {
+            RpcError::InvalidJson(_) => -32700,
+            RpcError::InvalidRequest => -32600,
+            RpcError::UnknownMethod(_) => -32601,
+            RpcError::InvalidParams(_) | RpcError::InvalidCall(_) => -32602,
+        }
+    }
+}
+
+pub struct McpServer<'a> {
+    runner: ToolRunner<'a>,
+}
+
+impl<'a> McpServer<'a> {
+    /// The server with the commands of the modules the config doesn't turn off, the CLI's own commands aren't tools
+    pub fn new(registry: &'a ModuleRegistry, paths: &'a Paths, config: Config) -> Self {
+        Self {
+            runner: ToolRunner::new(registry, paths, config),
+        }
+    }
+
+    /// Answers the messages on stdin until it's closed. stdin stays locked meanwhile, so a command waiting for input
+    /// (like authorizing without a browser) can't take the messages
+    pub fn serve(&self) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(response) = self.handle(&line) {
+                writeln!(stdout, "{}", response)?;
+                stdout.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The response to a message, `None` for notifications
+    pub fn handle(&self, message: &str) -> Option<Value> {
+        let request: Value = match serde_json::from_str(message) {
+            Ok(request) => request,
+            Err(err) => return Some(error_response(Value::Null, RpcError::InvalidJson(err))),
+        };
+        let id = request.get("id").cloned();
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            return Some(error_response(id.unwrap_or_default(), RpcError::InvalidRequest));
+        };
+        let params = request.get("params").cloned().unwrap_or_else(|| json!({}));
+        let result = match method {
+            "initialize" => Ok(self.initialize(&params)),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(self.list_tools()),
+            "tools/call" => self.call_tool(&params),
+            // The client saying it's done initializing, and other notifications the server doesn't act on
+            _ if id.is_none() => return None,
+            _ => Err(RpcError::UnknownMethod(method.into())),
+        };
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(err) => error_response(id, err),
+        })
+    }
+
+    /// Agrees on the client's protocol version if the server speaks it, and otherwise offers its latest one
+    fn initialize(&self, params: &Value) -> Value {
+        let requested = params.get("protocolVersion").and_then(Value::as_str);
+        let version = PROTOCOL_VERSIONS
+            .into_iter()
+            .find(|version| Some(*version) == requested)
+            .unwrap_or(PROTOCOL_VERSIONS[0]);
+        json!({
+            "protocolVersion": version,
+            "capabilities": { "tools": {} },
+            "serverInfo": { "name": "aerial-utils", "version": env!("CARGO_PKG_VERSION") },
+        })
+    }
+
+    fn list_tools(&self) -> Value {
+        let tools = self.runner.tools().iter().map(|tool| {
+            json!({
+                "name": tool.name,
+                "description": tool.description,
+                "inputSchema": serde_json::to_value(&tool.parameters).unwrap(),
+            })
+        });
+        json!({ "tools": tools.collect::<Vec<_>>() })
+    }
+
+    /// A failing command is still a result, with the error for the model to see
+    fn call_tool(&self, params: &Value) -> Result<Value, RpcError> {
+        let name = params
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or(RpcError::InvalidParams("the tool's `name` is missing"))?;
+        let arguments = match params.get("arguments") {
+            None | Some(Value::Null) => Map::new(),
+            Some(Value::Object(arguments)) => arguments.clone(),
+            Some(_) => return Err(RpcError::InvalidParams("`arguments` isn't an object")),
+        };
+        let (text, is_error) = match self.runner.call(name, &arguments) {
+            Ok(output) => (output, false),
+            Err(ToolError::Failed(err)) => (OutputFormat::Json.render(&ErrorOutput::new(err.kind(), &err)), true),
+            Err(err) => return Err(RpcError::InvalidCall(err)),
+        };
+        Ok(json!({ "content": [{ "type": "text", "text": text }], "isError": is_error }))
+    }
+}
+
+fn error_response(id: Value, err: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": err.code(), "message": err.to_string() } })
+}
@@ -1,7 +1,25 @@
+//! The functionality of Aerial, split into modules that each become a subcommand of the CLI
+
+#[cfg(feature = "cli")]
 mod module;
 pub mod music;
+#[cfg(feature = "cli")]
+pub mod plugin;
+#[cfg(feature = "cli")]
+mod registry;
+#[cfg(feature = "cli")]
 mod spec_gen;
 
+#[cfg(all(test, feature = "cli"))]
+mod tests;
+
+#[cfg(feature = "cli")]
 pub use module::*;
+#[cfg(feature = "cli")]
 pub use music::Music;
-pub use spec_gen::print_subcommand_specs;
+#[cfg(feature = "cli")]
+pub use registry::{ModuleEntry, ModuleRegistry};
+#[cfg(feature = "cli")]
+pub(crate) use spec_gen::{closest, did_you_mean};
+#[cfg(feature = "cli")]
+pub use spec_gen::{print_subcommand_specs, tools, SpecFormat, Tool, ToolCallError};
@@ -1,9 +1,25 @@
-use crate::utils::{Cache, Config};
+use crate::utils::{cache::CacheSection, config::ConfigSection};
+use serde::Serialize;
 use std::fmt::Display;
 
-pub trait Module: Display {
-    type Args: clap::Args;
-    type Error: std::error::Error;
+/// A module of the CLI, registered in a `ModuleRegistry`
+pub trait Module: Display + Default {
+    /// The module's subcommand
+    const NAME: &'static str;
+    /// The subcommand's description in the help
+    const ABOUT: &'static str;
+    type Args: clap::Args + clap::FromArgMatches;
+    /// The module's section of the config, the module can be turned off there with `enabled = false`
+    type Config: ConfigSection;
+    /// The module's section of the cache, written back after the command even if it failed
+    type Cache: CacheSection;
+    /// The result of a command, printed in the format chosen with `--output`
+    type Output: Serialize + Display;
+    type Error: std::error::Error + 'static;
 
-    fn run(args: Self::Args, config: &Config, cache: &mut Cache) -> Result<(), Self::Error>;
+    /// A stable name for the error in the serialized output formats
+    fn error_kind(err: &Self::Error) -> &'static str;
+
+    /// Runs the command in `args`. It shouldn't print to stdout itself, since the result isn't always printed there
+    fn run(args: Self::Args, config: &Self::Config, cache: &mut Self::Cache) -> Result<Self::Output, Self::Error>;
 }
@@ -1,9 +1,21 @@
+//! Controlling and looking up music, through Spotify
+
+#[cfg(feature = "cli")]
+#[allow(clippy::module_inception)]
 mod music;
 mod music_client;
-mod spotify;
+mod presentation;
+pub mod spotify;
 mod token;
+pub mod types;
+
+#[cfg(all(test, feature = "cli"))]
+mod tests;
 
+#[cfg(feature = "cli")]
 pub use music::*;
 pub use music_client::*;
+pub use presentation::MusicOutput;
 pub use spotify::spotify_auth::*;
+pub use spotify::spotify_client::{SpotifyClient, SpotifyError};
 pub use token::Token;
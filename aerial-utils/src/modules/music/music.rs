@@ -1,15 +1,21 @@
 use super::{
+    presentation::{MusicOutput, Profile},
     spotify::{
         spotify_api_spec::{ShuffleState, SpotifySearchType, SpotifyTimeRange},
         spotify_client::{SpotifyClient, SpotifyError},
     },
-    AuthError, MusicClient, SpotifyAuthClient,
+    AuthError, MusicClient, PageArgs, SpotifyAuthClient,
 };
 use crate::{
     modules::Module,
-    utils::{cache::Cache, config::SpotifyConfig, Config},
+    utils::{
+        cache::SpotifyCache,
+        config::{ConfigError, SpotifyConfig},
+        output::{Done, List},
+    },
 };
 use clap::{Args, Subcommand};
+use reqwest::blocking::Client;
 use std::fmt::Display;
 use thiserror::Error;
 
@@ -17,6 +23,9 @@ use thiserror::Error;
 pub struct MusicArgs {
     #[command(subcommand)]
     command: MusicCommands,
+    /// The Spotify account to use, one of the profiles in the config (the default profile if not given)
+    #[arg(long, global = true)]
+    profile: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -40,6 +49,8 @@ pub enum MusicCommands {
         /// The type of results to be searched for
         #[clap(short = 't', long, default_value_t, value_enum)]
         search_type: SpotifySearchType,
+        #[command(flatten)]
+        page: PageArgs,
     },
     /// Sets the shuffle state to the given parameter
     SetShuffle {
@@ -56,13 +67,30 @@ pub enum MusicCommands {
     TopTracks {
         #[clap(short = 't', long, default_value_t, value_enum)]
         time_range: SpotifyTimeRange,
+        #[command(flatten)]
+        page: PageArgs,
     },
     /// Print information about the current track
     CurrTrack,
     /// Initialize authentication to Spotify
-    Auth,
+    Auth {
+        /// Print the authorization URL instead of opening a browser, for remote or headless machines
+        #[arg(long)]
+        no_browser: bool,
+    },
     /// Remove authentication to Spotify
     Unauth,
+    /// Manage the Spotify accounts in the config
+    Profiles {
+        #[command(subcommand)]
+        command: ProfilesCommands,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ProfilesCommands {
+    /// List the profiles and whether they are authenticated
+    List,
 }
 
 #[derive(Args)]
@@ -78,53 +106,115 @@ pub struct PlayArgs {
 
 #[derive(Error, Debug)]
 pub enum MusicError {
-    #[error("No configuration for Spotify found in the config file")]
-    MissingConfig,
+    #[error("No profile named `{0}` in the Spotify config")]
+    UnknownProfile(String),
     #[error("Failed to perform action: {0}")]
     FailedAction(SpotifyError),
     #[error("Failed to authenticate to API: {0}")]
     FailedAuth(AuthError),
+    #[error("Failed to create the HTTP client, check the `http` section of the config: {0}")]
+    FailedToCreateHttpClient(reqwest::Error),
+    #[error("{0}")]
+    InvalidConfig(ConfigError),
 }
 
+impl MusicError {
+    /// A stable name for the error in the serialized output formats
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MusicError::UnknownProfile(_) => "unknown_profile",
+            MusicError::FailedAction(err) => err.kind(),
+            MusicError::FailedAuth(_) => "failed_auth",
+            MusicError::FailedToCreateHttpClient(_) => "http_client_error",
+            MusicError::InvalidConfig(_) => "config_error",
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct Music {}
 
 impl Music {
-    fn generate_client(config: &SpotifyConfig, cache: &mut Cache) -> Result<SpotifyClient, MusicError> {
-        Ok(SpotifyClient::new(config, cache).map_err(MusicError::FailedAuth)?)
+    fn generate_client(config: &SpotifyConfig, cache: &SpotifyCache, http_client: Client) -> Result<SpotifyClient, MusicError> {
+        SpotifyClient::new(config, cache, http_client).map_err(MusicError::FailedAuth)
+    }
+
+    fn profiles(config: &SpotifyConfig, cache: &SpotifyCache) -> Vec<Profile> {
+        config
+            .profile_names()
+            .into_iter()
+            .map(|name| Profile {
+                name: name.into(),
+                is_default: name == config.default_profile,
+                is_authenticated: cache.get_token(name).is_some(),
+            })
+            .collect()
+    }
+
+    fn run_command<C: MusicClient>(music_client: &C, command: MusicCommands) -> Result<MusicOutput, C::Error> {
+        let done = |_| MusicOutput::Done(Done);
+        Ok(match command {
+            MusicCommands::Toggle => music_client.toggle().map(done)?,
+            MusicCommands::Pause => music_client.pause().map(done)?,
+            MusicCommands::Resume => music_client.resume().map(done)?,
+            MusicCommands::Play(args) => music_client.play(args.track, args.context).map(done)?,
+            MusicCommands::Next => music_client.goto_next_track().map(done)?,
+            MusicCommands::Prev => music_client.goto_prev_track().map(done)?,
+            MusicCommands::SetShuffle { state } => music_client.set_shuffle_state(&state).map(done)?,
+            MusicCommands::Save { ids } => music_client.save_tracks(ids).map(done)?,
+            MusicCommands::TopTracks { time_range, page } => MusicOutput::Tracks(List(music_client.get_top_tracks(time_range, page)?)),
+            MusicCommands::Search { query, search_type, page } => music_client.search(query, search_type, page)?.into(),
+            MusicCommands::CurrTrack => MusicOutput::NowPlaying(Box::new(music_client.get_current_track()?)),
+            MusicCommands::Auth { .. } | MusicCommands::Unauth | MusicCommands::Profiles { .. } => unreachable!(),
+        })
     }
 }
 
 impl Module for Music {
+    const NAME: &'static str = "music";
+    const ABOUT: &'static str = "The music module";
     type Args = MusicArgs;
+    type Config = SpotifyConfig;
+    type Cache = SpotifyCache;
+    type Output = MusicOutput;
     type Error = MusicError;
 
-    fn run(args: Self::Args, config: &Config, cache: &mut Cache) -> Result<(), Self::Error> {
-        let spotify_config = config.modules.spotify.as_ref().ok_or(MusicError::MissingConfig)?;
+    fn error_kind(err: &Self::Error) -> &'static str {
+        err.kind()
+    }
 
-        if let MusicCommands::Auth = args.command {
-            return SpotifyAuthClient::add_auth_to_cache(cache, &spotify_config.client_id.as_str(), &spotify_config.client_secret.as_str())
-                .map_err(SpotifyError::FailedInitialAuth)
-                .map_err(MusicError::FailedAction);
+    fn run(args: Self::Args, spotify_config: &SpotifyConfig, cache: &mut SpotifyCache) -> Result<MusicOutput, Self::Error> {
+        if let MusicCommands::Profiles {
+            command: ProfilesCommands::List,
+        } = args.command
+        {
+            return Ok(MusicOutput::Profiles(List(Self::profiles(spotify_config, cache))));
         }
-
-        let music_client = Self::generate_client(spotify_config, cache)?;
+        let spotify_config = &spotify_config
+            .with_profile(args.profile.as_deref())
+            .map_err(MusicError::InvalidConfig)?
+            .ok_or_else(|| MusicError::UnknownProfile(args.profile.unwrap_or_else(|| spotify_config.default_profile.clone())))?;
+        let http_client = spotify_config.http.build_client().map_err(MusicError::FailedToCreateHttpClient)?;
 
         match args.command {
-            MusicCommands::Toggle => music_client.toggle(),
-            MusicCommands::Pause => music_client.pause(),
-            MusicCommands::Resume => music_client.resume(),
-            MusicCommands::Play(args) => music_client.play(args.track, args.context),
-            MusicCommands::Next => music_client.goto_next_track(),
-            MusicCommands::Prev => music_client.goto_prev_track(),
-            MusicCommands::Unauth => Ok(SpotifyAuthClient::remove_auth_from_cache(cache)),
-            MusicCommands::SetShuffle { state } => music_client.set_shuffle_state(&state),
-            MusicCommands::Save { ids } => music_client.save_tracks(ids),
-            MusicCommands::TopTracks { time_range } => music_client.get_top_tracks(time_range),
-            MusicCommands::Search { query, search_type } => music_client.search(query.clone(), search_type),
-            MusicCommands::CurrTrack => music_client.print_current_track(),
-            _ => unreachable!(),
+            MusicCommands::Auth { no_browser } => {
+                SpotifyAuthClient::add_auth_to_cache(cache, spotify_config, &http_client, !no_browser)
+                    .map_err(SpotifyError::FailedInitialAuth)
+                    .map_err(MusicError::FailedAction)?;
+                return Ok(MusicOutput::Done(Done));
+            }
+            MusicCommands::Unauth => {
+                SpotifyAuthClient::remove_auth_from_cache(cache, spotify_config);
+                return Ok(MusicOutput::Done(Done));
+            }
+            _ => (),
         }
-        .map_err(MusicError::FailedAction)
+
+        let music_client = Self::generate_client(spotify_config, cache, http_client)?;
+        let res = Self::run_command(&music_client, args.command);
+        // Saved even if the command failed, since the token may have been refreshed before the failure
+        music_client.api_handler.auth.save_to_cache(cache);
+        res.map_err(MusicError::FailedAction)
     }
 }
 
@@ -1,6 +1,25 @@
-use super::spotify::spotify_api_spec::{ShuffleState, SpotifySearchType, SpotifyTimeRange};
+use super::{
+    spotify::spotify_api_spec::{ShuffleState, SpotifySearchType, SpotifyTimeRange},
+    types::{NowPlaying, SearchResults, Track},
+};
 use std::error::Error;
 
+/// Which part of a list of results to get, by default the first page of it
+#[cfg_attr(feature = "cli", derive(clap::Args))]
+#[derive(Clone, Copy, Default)]
+pub struct PageArgs {
+    /// The maximum number of results to get (up to 50)
+    #[cfg_attr(feature = "cli", arg(short, long, value_parser = clap::value_parser!(u32).range(1..=50)))]
+    pub limit: Option<u32>,
+    /// The index of the first result to get
+    #[cfg_attr(feature = "cli", arg(short, long))]
+    pub offset: Option<u32>,
+    /// Get all the results (up to the configured maximum) instead of a single page
+    #[cfg_attr(feature = "cli", arg(short, long))]
+    pub all: bool,
+}
+
+/// Music playback and lookup, implemented for a music service like Spotify
 pub trait MusicClient {
     type Error: Error;
 
@@ -11,8 +30,8 @@ pub trait MusicClient {
     fn goto_next_track(&self) -> Result<(), Self::Error>;
     fn goto_prev_track(&self) -> Result<(), Self::Error>;
     fn set_shuffle_state(&self, state: &ShuffleState) -> Result<(), Self::Error>;
-    fn search(&self, query: String, search_type: SpotifySearchType) -> Result<(), Self::Error>;
+    fn search(&self, query: String, search_type: SpotifySearchType, page: PageArgs) -> Result<SearchResults, Self::Error>;
     fn save_tracks(&self, ids: Vec<String>) -> Result<(), Self::Error>;
-    fn get_top_tracks(&self, time_range: SpotifyTimeRange) -> Result<(), Self::Error>;
-    fn print_current_track(&self) -> Result<(), Self::Error>;
+    fn get_top_tracks(&self, time_range: SpotifyTimeRange, page: PageArgs) -> Result<Vec<Track>, Self::Error>;
+    fn get_current_track(&self) -> Result<NowPlaying, Self::Error>;
 }
@@ -0,0 +1,142 @@
+//! How the music values are shown to the user, the serialized formats use their `Serialize` impls directly
+
+use super::types::{Album, Artist, Context, NowPlaying, Playlist, SearchResults, Track};
+use crate::utils::output::{Done, List};
+use serde::Serialize;
+use std::fmt::Display;
+
+/// The result of a music command, as printed by `Music::run`
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum MusicOutput {
+    Done(Done),
+    Tracks(List<Track>),
+    Albums(List<Album>),
+    Artists(List<Artist>),
+    Playlists(List<Playlist>),
+    NowPlaying(Box<NowPlaying>),
+    Profiles(List<Profile>),
+}
+
+/// A Spotify account in the config
+#[derive(Serialize)]
+pub struct Profile {
+    pub name: String,
+    pub is_default: bool,
+    /// Whether the cache holds a token for it
+    pub is_authenticated: bool,
+}
+
+impl From<SearchResults> for MusicOutput {
+    fn from(results: SearchResults) -> Self {
+        match results {
+            SearchResults::Tracks(tracks) => MusicOutput::Tracks(List(tracks)),
+            SearchResults::Albums(albums) => MusicOutput::Albums(List(albums)),
+            SearchResults::Artists(artists) => MusicOutput::Artists(List(artists)),
+            SearchResults::Playlists(playlists) => MusicOutput::Playlists(List(playlists)),
+        }
+    }
+}
+
+impl Display for MusicOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MusicOutput::Done(done) => done.fmt(f),
+            MusicOutput::Tracks(tracks) => tracks.fmt(f),
+            MusicOutput::Albums(albums) => albums.fmt(f),
+            MusicOutput::Artists(artists) => artists.fmt(f),
+            MusicOutput::Playlists(playlists) => playlists.fmt(f),
+            MusicOutput::NowPlaying(now_playing) => now_playing.fmt(f),
+            MusicOutput::Profiles(profiles) => profiles.fmt(f),
+        }
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lines = [
+            format!("Name: {}{}", self.name, if self.is_default { " (default)" } else { "" }),
+            format!("Authenticated: {}", if self.is_authenticated { "Yes" } else { "No" }),
+        ];
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl Display for NowPlaying {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let track_info = match &self.track {
+            Some(track) => format!("{}", track),
+            None => "No track information available".to_string(),
+        };
+
+        let context_info = match &self.context {
+            Some(context) => format!("CONTEXT:\n{}", context),
+            None => "No context information available".to_string(),
+        };
+
+        write!(f, "{}\n\n{}", track_info, context_info)
+    }
+}
+
+impl Display for Playlist {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lines = [
+            format!("Name: {}", self.name),
+            format!("Description: {}", self.description),
+            format!("ID: {}", self.id),
+            format!("Public: {}", if self.is_public { "Yes" } else { "No" }),
+        ];
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl Display for Artist {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lines = [
+            format!("Name: {}", self.name),
+            format!("Genres: {}", self.genres.join(", ")),
+            format!("ID: {}", self.id),
+        ];
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl Display for Track {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let artist_names: Vec<&str> = self.artists.iter().map(|artist| artist.name.as_str()).collect();
+        let lines = [
+            format!("Name: {}", self.name),
+            format!("Album: {} (ID = {})", self.album.name, self.album.id),
+            format!("Artist(s): {}", artist_names.join(", ")),
+            format!("ID: {}", self.id),
+        ];
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl Display for Context {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lines = [
+            format!("URI: {}", self.uri),
+            format!("Href: {}", self.href),
+            format!("External URLs: {:?}", self.external_urls),
+            format!("Context Type: {}", self.context_type),
+        ];
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl Display for Album {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let artist_names: Vec<&str> = self.artists.iter().map(|artist| artist.name.as_str()).collect();
+        let lines = [
+            format!("Name: {}", self.name),
+            format!("Album: {}", self.album_type),
+            format!("Length: {} Tracks", self.total_tracks),
+            format!("Artist(s): {}", artist_names.join(", ")),
+            format!("Release Date: {}", self.release_date),
+            format!("ID: {}", self.id),
+        ];
+        write!(f, "{}", lines.join("\n"))
+    }
+}
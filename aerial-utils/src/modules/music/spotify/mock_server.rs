@@ -0,0 +1,449 @@
+//! A stateful stand-in for the Spotify Web API and accounts service, for tests that run the music module end to end
+
+use crate::{
+    modules::music::Token,
+    utils::{
+        cache::SpotifyCache,
+        config::{SpotifyConfig, DEFAULT_PROFILE},
+    },
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as base64_url_engine, Engine as _};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+use tiny_http::{Header, Method, Request, Response, Server};
+use url::Url;
+
+pub const MOCK_CLIENT_ID: &str = "mock-client-id";
+pub const MOCK_AUTH_CODE: &str = "mock-auth-code";
+const MOCK_REFRESH_TOKEN: &str = "mock-refresh-token";
+
+type MockResponse = Response<std::io::Cursor<Vec<u8>>>;
+
+pub struct MockState {
+    /// Whether there's a device playback commands can be sent to
+    pub active_device: bool,
+    pub is_playing: bool,
+    pub shuffle: bool,
+    /// Index of the current track in the catalog
+    pub current_track: usize,
+    pub context: Option<String>,
+    pub saved_tracks: Vec<String>,
+    pub access_token: String,
+    pub tokens_issued: u32,
+    /// The PKCE challenge sent to `/authorize`, exchanging the code then needs its verifier
+    pub code_challenge: Option<String>,
+    /// Every request received, formatted as `METHOD /path`
+    pub requests: Vec<String>,
+    /// The query parameters of every request received, in the same order as `requests`
+    pub params: Vec<HashMap<String, String>>,
+    /// The addresses requests came from, a new one for every connection the client opened
+    pub connections: HashSet<SocketAddr>,
+    /// Errors returned instead of the normal response, each one is used once
+    pub failures: Vec<MockFailure>,
+}
+
+pub struct MockFailure {
+    /// The request to fail, formatted as `METHOD /path`
+    pub request: String,
+    pub status: u16,
+    pub message: String,
+    pub reason: Option<String>,
+    pub retry_after: Option<u64>,
+}
+
+impl MockFailure {
+    pub fn new(request: &str, status: u16) -> Self {
+        Self {
+            request: request.into(),
+            status,
+            message: "Mock failure".into(),
+            reason: None,
+            retry_after: None,
+        }
+    }
+}
+
+impl Default for MockState {
+    fn default() -> Self {
+        Self {
+            active_device: true,
+            is_playing: false,
+            shuffle: false,
+            current_track: 0,
+            context: None,
+            saved_tracks: Vec::new(),
+            access_token: "mock-access-token".into(),
+            tokens_issued: 0,
+            code_challenge: None,
+            requests: Vec::new(),
+            params: Vec::new(),
+            connections: HashSet::new(),
+            failures: Vec::new(),
+        }
+    }
+}
+
+pub struct MockSpotify {
+    server: Arc<Server>,
+    state: Arc<Mutex<MockState>>,
+    handle: Option<JoinHandle<()>>,
+    url: String,
+}
+
+impl MockSpotify {
+    pub fn start() -> Self {
+        Self::start_with(MockState::default())
+    }
+
+    pub fn start_with(state: MockState) -> Self {
+        let server = Arc::new(Server::http("127.0.0.1:0").expect("Failed to start the mock Spotify server"));
+        let url = format!("http://{}", server.server_addr());
+        let state = Arc::new(Mutex::new(state));
+
+        let (thread_server, thread_state) = (server.clone(), state.clone());
+        let handle = thread::spawn(move || {
+            for request in thread_server.incoming_requests() {
+                handle_request(request, &thread_state);
+            }
+        });
+
+        Self {
+            server,
+            state,
+            handle: Some(handle),
+            url,
+        }
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn config(&self) -> SpotifyConfig {
+        let raw_config = format!(
+            "client_id = \"{}\"\napi_url = \"{}/v1\"\naccounts_url = \"{}\"",
+            MOCK_CLIENT_ID, self.url, self.url
+        );
+        toml::from_str(&raw_config).unwrap()
+    }
+
+    /// A cache holding a token the server accepts
+    pub fn cache(&self) -> SpotifyCache {
+        self.cache_with_token_age(Duration::ZERO)
+    }
+
+    /// A cache holding a token that is past its expiration time, so it has to be refreshed before it can be used
+    pub fn expired_cache(&self) -> SpotifyCache {
+        self.cache_with_token_age(Duration::from_secs(7200))
+    }
+
+    pub fn cache_with_token_age(&self, age: Duration) -> SpotifyCache {
+        let mut cache = SpotifyCache::default();
+        let token = Token {
+            access_token: self.state().access_token.clone(),
+            token_type: "Bearer".into(),
+            expires_in: Duration::from_secs(3600),
+            time_set: SystemTime::now() - age,
+            refresh_token: MOCK_REFRESH_TOKEN.into(),
+        };
+        cache.set_token(DEFAULT_PROFILE, Some(token));
+        cache
+    }
+
+    pub fn received(&self, request: &str) -> bool {
+        self.state().requests.iter().any(|r| r == request)
+    }
+}
+
+impl Drop for MockSpotify {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn handle_request(mut request: Request, state: &Mutex<MockState>) {
+    // Kept as the client sent it, so that links to other pages point back to this server
+    let host = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Host"))
+        .map_or("localhost".into(), |h| h.value.to_string());
+    let url = Url::parse(&format!("http://{}{}", host, request.url())).unwrap();
+    let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    let mut body = String::new();
+    let _ = request.as_reader().read_to_string(&mut body);
+    let auth = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .map(|h| h.value.to_string());
+
+    let mut state = state.lock().unwrap();
+    let request_name = format!("{} {}", request.method(), url.path());
+    state.requests.push(request_name.clone());
+    state.params.push(params.clone());
+    state.connections.extend(request.remote_addr().copied());
+    if let Some(index) = state.failures.iter().position(|failure| failure.request == request_name) {
+        let failure = state.failures.remove(index);
+        let mut response = error_response(failure.status, &failure.message, failure.reason.as_deref());
+        if let Some(retry_after) = failure.retry_after {
+            response.add_header(Header::from_bytes("Retry-After", retry_after.to_string()).unwrap());
+        }
+        let _ = request.respond(response);
+        return;
+    }
+
+    let response = match url.path().strip_prefix("/v1/") {
+        Some(_) if !is_authorized(auth.as_deref(), &state) => error_response(401, "The access token expired", None),
+        Some(endpoint) => api_response(request.method(), endpoint, &url, &params, &body, &mut state),
+        None => accounts_response(request.method(), url.path(), &params, &body, &mut state),
+    };
+    let _ = request.respond(response);
+}
+
+fn is_authorized(auth: Option<&str>, state: &MockState) -> bool {
+    auth.and_then(|auth| auth.split_whitespace().last()) == Some(state.access_token.as_str())
+}
+
+fn api_response(method: &Method, endpoint: &str, url: &Url, params: &HashMap<String, String>, body: &str, state: &mut MockState) -> MockResponse {
+    let needs_device = !matches!(
+        endpoint,
+        "me/player" | "me/player/currently-playing" | "search" | "me/tracks" | "me/top/tracks"
+    );
+    if needs_device && !state.active_device {
+        return error_response(404, "Player command failed: No active device found", Some("NO_ACTIVE_DEVICE"));
+    }
+
+    match (method, endpoint) {
+        (Method::Get, "me/player") if !state.active_device => no_content(),
+        (Method::Get, "me/player") => json_response(json!({
+            "device": { "id": "mock-device", "is_active": true, "name": "Mock Speaker", "type": "Speaker" },
+            "is_playing": state.is_playing,
+            "shuffle_state": state.shuffle,
+        })),
+        (Method::Get, "me/player/currently-playing") if !state.active_device => no_content(),
+        (Method::Get, "me/player/currently-playing") => json_response(json!({
+            "item": catalog_tracks()[state.current_track],
+            "context": state.context.as_ref().map(|uri| json!({
+                "uri": uri,
+                "href": "https://api.spotify.com/v1/mock",
+                "external_urls": { "spotify": "https://open.spotify.com/mock" },
+                "type": uri.split(':').nth(1).unwrap_or("album"),
+            })),
+        })),
+        (Method::Put, "me/player/pause") => {
+            state.is_playing = false;
+            no_content()
+        }
+        (Method::Put, "me/player/play") => {
+            let body: Value = serde_json::from_str(body).unwrap_or_default();
+            if let Some(uri) = body["uris"][0].as_str().or(body["offset"]["uri"].as_str()) {
+                match catalog_tracks().iter().position(|track| track["uri"] == uri) {
+                    Some(index) => state.current_track = index,
+                    None => return error_response(400, "Invalid track uri", None),
+                }
+            }
+            if let Some(context) = body["context_uri"].as_str() {
+                state.context = Some(context.into());
+            }
+            state.is_playing = true;
+            no_content()
+        }
+        (Method::Post, "me/player/next") => {
+            state.current_track = (state.current_track + 1) % catalog_tracks().len();
+            no_content()
+        }
+        (Method::Post, "me/player/previous") => {
+            state.current_track = state.current_track.checked_sub(1).unwrap_or(catalog_tracks().len() - 1);
+            no_content()
+        }
+        (Method::Put, "me/player/shuffle") => match params.get("state").map(String::as_str) {
+            Some("true") => {
+                state.shuffle = true;
+                no_content()
+            }
+            Some("false") => {
+                state.shuffle = false;
+                no_content()
+            }
+            _ => error_response(400, "Missing or invalid state", None),
+        },
+        (Method::Put, "me/tracks") => {
+            let body: Value = serde_json::from_str(body).unwrap_or_default();
+            let Some(ids) = body["ids"].as_array() else {
+                return error_response(400, "Missing ids", None);
+            };
+            state.saved_tracks.extend(ids.iter().filter_map(|id| id.as_str().map(String::from)));
+            Response::from_data(Vec::new()).with_status_code(200)
+        }
+        (Method::Get, "search") => search_response(url, params),
+        (Method::Get, "me/top/tracks") => match paged(catalog_tracks(), url, params) {
+            Some(page) => json_response(page),
+            None => error_response(400, "Invalid limit or offset", None),
+        },
+        _ => error_response(404, "Service not found", None),
+    }
+}
+
+fn search_response(url: &Url, params: &HashMap<String, String>) -> MockResponse {
+    let query = params.get("q").map(|q| q.to_lowercase()).unwrap_or_default();
+    let matches = |items: Vec<Value>| -> Option<Value> {
+        let items: Vec<Value> = items
+            .into_iter()
+            .filter(|item| item["name"].as_str().unwrap_or_default().to_lowercase().contains(&query))
+            .collect();
+        paged(items, url, params)
+    };
+
+    let mut results = serde_json::Map::new();
+    for search_type in params.get("type").map(String::as_str).unwrap_or_default().split(',') {
+        let (key, items) = match search_type {
+            "track" => ("tracks", catalog_tracks()),
+            "album" => ("albums", vec![catalog_album()]),
+            "artist" => ("artists", vec![catalog_artist()]),
+            "playlist" => ("playlists", vec![catalog_playlist()]),
+            _ => return error_response(400, "Bad search type field", None),
+        };
+        let Some(page) = matches(items) else {
+            return error_response(400, "Invalid limit or offset", None);
+        };
+        results.insert(key.into(), page);
+    }
+    json_response(Value::Object(results))
+}
+
+/// A page of `items` as selected by the `limit` (default 20, at most 50) and `offset` params, `None` if they're invalid.
+/// Its `next` link is `url` with the offset moved past this page
+fn paged(items: Vec<Value>, url: &Url, params: &HashMap<String, String>) -> Option<Value> {
+    let limit: usize = params.get("limit").map_or(Some(20), |limit| limit.parse().ok())?;
+    let offset: usize = params.get("offset").map_or(Some(0), |offset| offset.parse().ok())?;
+    if !(1..=50).contains(&limit) {
+        return None;
+    }
+
+    let total = items.len();
+    let next = (offset + limit < total).then(|| {
+        let mut next = url.clone();
+        let pairs: Vec<(String, String)> = url.query_pairs().into_owned().filter(|(key, _)| key != "offset").collect();
+        next.query_pairs_mut()
+            .clear()
+            .extend_pairs(pairs)
+            .append_pair("offset", &(offset + limit).to_string());
+        next.to_string()
+    });
+    Some(json!({
+        "items": items.into_iter().skip(offset).take(limit).collect::<Vec<Value>>(),
+        "total": total,
+        "limit": limit,
+        "offset": offset,
+        "href": url.to_string(),
+        "previous": null,
+        "next": next,
+    }))
+}
+
+fn accounts_response(method: &Method, path: &str, params: &HashMap<String, String>, body: &str, state: &mut MockState) -> MockResponse {
+    match (method, path) {
+        // Acts like the user already approved the app, so the browser is sent straight back with a code
+        (Method::Get, "/authorize") => {
+            let Some(mut redirect) = params.get("redirect_uri").and_then(|uri| Url::parse(uri).ok()) else {
+                return error_response(400, "Invalid redirect URI", None);
+            };
+            state.code_challenge = params.get("code_challenge").cloned();
+            redirect
+                .query_pairs_mut()
+                .append_pair("code", MOCK_AUTH_CODE)
+                .append_pair("state", params.get("state").map(String::as_str).unwrap_or_default());
+            Response::from_data(Vec::new())
+                .with_status_code(302)
+                .with_header(Header::from_bytes("Location", redirect.as_str()).unwrap())
+        }
+        (Method::Post, "/api/token") => {
+            let form: HashMap<String, String> = url::form_urlencoded::parse(body.as_bytes()).into_owned().collect();
+            let valid_grant = match form.get("grant_type").map(String::as_str) {
+                Some("refresh_token") => form.get("refresh_token").map(String::as_str) == Some(MOCK_REFRESH_TOKEN),
+                Some("authorization_code") => form.get("code").map(String::as_str) == Some(MOCK_AUTH_CODE) && verifies_challenge(&form, state),
+                _ => false,
+            };
+            if !valid_grant {
+                return json_response(json!({ "error": "invalid_grant", "error_description": "Invalid grant" })).with_status_code(400);
+            }
+            state.tokens_issued += 1;
+            state.access_token = format!("mock-access-token-{}", state.tokens_issued);
+            json_response(json!({
+                "access_token": state.access_token,
+                "token_type": "Bearer",
+                "expires_in": 3600,
+                "refresh_token": MOCK_REFRESH_TOKEN,
+            }))
+        }
+        _ => error_response(404, "Not found", None),
+    }
+}
+
+/// Whether the token request gives the verifier of the PKCE challenge, if the authorization had one
+fn verifies_challenge(form: &HashMap<String, String>, state: &MockState) -> bool {
+    let challenge = |verifier: &String| base64_url_engine.encode(Sha256::digest(verifier.as_bytes()));
+    state.code_challenge.is_none() || form.get("code_verifier").map(challenge) == state.code_challenge
+}
+
+fn json_response(body: Value) -> MockResponse {
+    Response::from_string(body.to_string()).with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+fn no_content() -> MockResponse {
+    Response::from_data(Vec::new()).with_status_code(204)
+}
+
+fn error_response(status: u16, message: &str, reason: Option<&str>) -> MockResponse {
+    let mut error = json!({ "status": status, "message": message });
+    if let Some(reason) = reason {
+        error["reason"] = reason.into();
+    }
+    json_response(json!({ "error": error })).with_status_code(status)
+}
+
+pub fn catalog_tracks() -> Vec<Value> {
+    [("1", "Mock Song"), ("2", "Another Mock Song"), ("3", "Last Mock Song")]
+        .into_iter()
+        .map(|(id, name)| {
+            json!({
+                "name": name,
+                "id": format!("mock-track-{}", id),
+                "uri": format!("spotify:track:mock-track-{}", id),
+                "album": catalog_album(),
+                "artists": [{ "name": "Mock Artist" }],
+            })
+        })
+        .collect()
+}
+
+fn catalog_album() -> Value {
+    json!({
+        "name": "Mock Album",
+        "id": "mock-album",
+        "album_type": "album",
+        "total_tracks": 3,
+        "artists": [{ "name": "Mock Artist" }],
+        "release_date": "2024-01-01",
+        "release_date_precision": "day",
+    })
+}
+
+fn catalog_artist() -> Value {
+    json!({ "name": "Mock Artist", "id": "mock-artist", "genres": ["mock rock"] })
+}
+
+fn catalog_playlist() -> Value {
+    json!({ "name": "Mock Playlist", "id": "mock-playlist", "description": "Songs for tests", "public": true })
+}
@@ -1,3 +1,7 @@
+//! The Spotify Web API: its endpoints, authorization, and the `MusicClient` implemented with them
+
+#[cfg(all(test, feature = "cli"))]
+pub mod mock_server;
 pub mod spotify_api_handler;
 pub mod spotify_api_spec;
 pub mod spotify_auth;
@@ -2,34 +2,62 @@ use super::spotify_auth::{AuthError, SpotifyAuthClient};
 use crate::utils::{
     api_handler::ApiHandler,
     auth_client::AddAuthExt,
+    cache::SpotifyCache,
     config::SpotifyConfig,
     http::{ExtractFromResposneExt, ResponseError, ValidateResponseExt},
-    ApiRequestSpec, Cache,
+    retry::RetryPolicy,
+    ApiRequestSpec,
+};
+use reqwest::{
+    blocking::Client,
+    header::{CONTENT_LENGTH, CONTENT_TYPE},
+    StatusCode,
 };
-use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE};
 use serde::{de::DeserializeOwned, Serialize};
-
-const API_ENDPOINT: &str = "https://api.spotify.com/v1";
+use std::thread;
 
 pub struct SpotifyApiHandler {
     pub auth: SpotifyAuthClient,
+    client: Client,
+    api_endpoint: String,
+    retry: RetryPolicy,
 }
 
 impl SpotifyApiHandler {
-    pub fn new(config: &SpotifyConfig, cache: &mut Cache) -> Result<Self, AuthError> {
+    pub fn new(config: &SpotifyConfig, cache: &SpotifyCache, client: Client) -> Result<Self, AuthError> {
         Ok(Self {
-            auth: SpotifyAuthClient::new(cache, config.client_id.as_str(), config.client_secret.as_str())?,
+            auth: SpotifyAuthClient::new(cache, config, client.clone())?,
+            client,
+            api_endpoint: config.api_url.clone(),
+            retry: config.retry.clone(),
         })
     }
 }
 
 impl ApiHandler for SpotifyApiHandler {
     fn make_request<B: Serialize, R: DeserializeOwned>(&self, spec: &dyn ApiRequestSpec<Body = B, Resposne = R>) -> Result<R, ResponseError> {
-        let request = spec
-            .build(API_ENDPOINT)
-            .auth(&self.auth)
-            .header(CONTENT_TYPE, "application/json")
-            .header(CONTENT_LENGTH, 0);
-        Ok(request.send().validate()?.extract()?)
+        let retry_safe = spec.retry_safe();
+        let mut attempt = 0;
+        let mut refreshed_token = false;
+        loop {
+            let request = spec
+                .build(&self.client, &self.api_endpoint)
+                .map_err(ResponseError::InvalidRequest)?
+                .auth(&self.auth)
+                .header(CONTENT_TYPE, "application/json")
+                .header(CONTENT_LENGTH, 0);
+            let response = request.send();
+            // The token may be rejected before its local expiration time, one refresh is enough to tell if that's the case
+            if !refreshed_token && matches!(&response, Ok(response) if response.status() == StatusCode::UNAUTHORIZED) {
+                self.auth.refresh()?;
+                refreshed_token = true;
+                continue;
+            }
+            match self.retry.retry_delay(&response, attempt, retry_safe) {
+                Some(delay) => thread::sleep(delay),
+                None => return Ok(response.validate()?.extract()?),
+            }
+            attempt += 1;
+        }
     }
 }
@@ -1,13 +1,11 @@
 use crate::impl_endpoint;
-use crate::utils::api_spec::{NoBody, NoResponse, OptionalResponse};
-use crate::utils::ApiRequest;
-use crate::utils::ApiRequestSpec;
-use clap::ValueEnum;
+use crate::modules::music::types::{Album, Artist, ArtistName, Context, NowPlaying, Playlist, Track};
+use crate::utils::api_spec::{NoResponse, OptionalResponse};
+use crate::utils::paging::Paging;
 use reqwest::Method;
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Display;
-use std::u8;
 
 pub struct Pause;
 impl_endpoint!(Pause, Method::PUT, "me/player/pause", NoResponse);
@@ -65,14 +63,15 @@ fn setshuffle_params(args: &SetShuffle) -> HashMap<String, String> {
     [("state".into(), args.state.to_string())].into()
 }
 
-#[derive(Clone, ValueEnum, Copy)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy)]
 pub enum ShuffleState {
     On,
     Off,
 }
 
 impl ShuffleState {
-    pub fn into_bool(&self) -> bool {
+    pub fn into_bool(self) -> bool {
         match self {
             ShuffleState::On => true,
             ShuffleState::Off => false,
@@ -99,24 +98,46 @@ pub struct SpotifyDevice {
     pub device_type: String,
 }
 
+/// The page of a list endpoint to get, unset values are left to Spotify's defaults
+#[derive(Default, Clone, Copy)]
+pub struct PageRange {
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+impl PageRange {
+    fn add_params(&self, params: &mut HashMap<String, String>) {
+        if let Some(limit) = self.limit {
+            params.insert("limit".into(), limit.to_string());
+        }
+        if let Some(offset) = self.offset {
+            params.insert("offset".into(), offset.to_string());
+        }
+    }
+}
+
 pub struct Search {
     pub query: String,
     pub search_type: Vec<SpotifySearchType>,
+    pub page: PageRange,
 }
 
 impl_endpoint!(Search, Method::GET, "search" => search_params, SpotifySearchResults);
 fn search_params(args: &Search) -> HashMap<String, String> {
-    [
+    let mut params: HashMap<String, String> = [
         ("q".into(), args.query.clone()),
         (
             "type".into(),
             args.search_type.iter().map(|s| s.to_string()).collect::<Vec<String>>().join(","),
         ),
     ]
-    .into()
+    .into();
+    args.page.add_params(&mut params);
+    params
 }
 
-#[derive(clap::ValueEnum, Default, Clone, Debug, Serialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Default, Clone, Debug, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum SpotifySearchType {
     #[default]
@@ -140,49 +161,10 @@ impl Display for SpotifySearchType {
 
 #[derive(Deserialize)]
 pub struct SpotifySearchResults {
-    pub tracks: Option<SpotifySearchTracksResults>,
-    pub albums: Option<SpotifySearchAlbumsResults>,
-    pub artists: Option<SpotifySearchArtistsResults>,
-    pub playlists: Option<SpotifySearchPlaylistsResults>,
-}
-
-#[derive(Deserialize)]
-pub struct SpotifySearchTracksResults {
-    #[serde(alias = "total")]
-    pub tracks_num: u32,
-    #[serde(deserialize_with = "deserialize_vec_skip_null")]
-    pub items: Vec<SpotifyTrack>,
-}
-
-#[derive(Deserialize)]
-pub struct SpotifySearchAlbumsResults {
-    #[serde(alias = "total")]
-    pub tracks_num: u32,
-    #[serde(deserialize_with = "deserialize_vec_skip_null")]
-    pub items: Vec<SpotifySimplifiedAlbum>,
-}
-
-#[derive(Deserialize)]
-pub struct SpotifySearchArtistsResults {
-    #[serde(alias = "total")]
-    pub tracks_num: u32,
-    #[serde(deserialize_with = "deserialize_vec_skip_null")]
-    pub items: Vec<SpotifyArtist>,
-}
-
-#[derive(Deserialize)]
-pub struct SpotifySearchPlaylistsResults {
-    #[serde(alias = "total")]
-    pub tracks_num: u32,
-    #[serde(deserialize_with = "deserialize_vec_skip_null")]
-    pub items: Vec<SpotifySimplifiedPlaylist>,
-}
-
-pub fn spotify_search_results_to_string<T: Display>(results: Option<Vec<T>>) -> String {
-    match results {
-        Some(items) => items.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n\n"),
-        None => "No search results returned".into(),
-    }
+    pub tracks: Option<Paging<SpotifyTrack>>,
+    pub albums: Option<Paging<SpotifySimplifiedAlbum>>,
+    pub artists: Option<Paging<SpotifyArtist>>,
+    pub playlists: Option<Paging<SpotifySimplifiedPlaylist>>,
 }
 
 pub struct SaveTracks {
@@ -200,13 +182,17 @@ fn savetrack_body(args: &SaveTracks) -> SaveTrackBody {
 
 pub struct GetTopTracks {
     pub time_range: SpotifyTimeRange,
+    pub page: PageRange,
 }
 impl_endpoint!(GetTopTracks, Method::GET, "me/top/tracks" => get_top_tracks_params, TopTracksResponse);
 fn get_top_tracks_params(args: &GetTopTracks) -> HashMap<String, String> {
-    [("time_range".into(), args.time_range.to_string())].into()
+    let mut params: HashMap<String, String> = [("time_range".into(), args.time_range.to_string())].into();
+    args.page.add_params(&mut params);
+    params
 }
 
-#[derive(clap::ValueEnum, Default, Clone)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Default, Clone)]
 pub enum SpotifyTimeRange {
     Short, // ~4 Weeks
     #[default]
@@ -214,27 +200,18 @@ pub enum SpotifyTimeRange {
     Long,  // ~1 year
 }
 
-impl ToString for SpotifyTimeRange {
-    fn to_string(&self) -> String {
-        match self {
+impl Display for SpotifyTimeRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
             SpotifyTimeRange::Short => "short_term",
             SpotifyTimeRange::Medium => "medium_term",
             SpotifyTimeRange::Long => "long_term",
-        }.into()
+        };
+        write!(f, "{}", text)
     }
 }
 
-#[derive(Deserialize)]
-pub struct TopTracksResponse {
-    pub items: Vec<SpotifyTrack>,
-    pub total: u32,
-    pub limit: u32,
-    pub offset: u32,
-    pub href: String,
-    // TODO: Implement option to actually use this
-    pub previous: Option<String>,
-    pub next: Option<String>,
-}
+pub type TopTracksResponse = Paging<SpotifyTrack>;
 
 pub struct GetCurrentTrack;
 impl_endpoint!(GetCurrentTrack, Method::GET, "me/player/currently-playing", CurrentTrack);
@@ -245,22 +222,6 @@ pub struct CurrentTrack {
     pub context: Option<SpotifyContext>,
 }
 
-impl Display for CurrentTrack {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let track_info = match &self.item {
-            Some(track) => format!("{}", track),
-            None => "No track information available".to_string(),
-        };
-
-        let context_info = match &self.context {
-            Some(context) => format!("CONTEXT:\n{}", context),
-            None => "No context information available".to_string(),
-        };
-
-        write!(f, "{}\n\n{}", track_info, context_info)
-    }
-}
-
 // Other types -------------------------------------------------
 #[derive(Deserialize)]
 pub struct SpotifySimplifiedArtist {
@@ -276,18 +237,6 @@ pub struct SpotifySimplifiedPlaylist {
     pub is_public: bool,
 }
 
-impl Display for SpotifySimplifiedPlaylist {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let lines = [
-            format!("Name: {}", self.name),
-            format!("Description: {}", self.description),
-            format!("ID: {}", self.id),
-            format!("Public: {}", if self.is_public { "Yes" } else { "No" }),
-        ];
-        write!(f, "{}", lines.join("\n"))
-    }
-}
-
 #[derive(Deserialize)]
 pub struct SpotifyArtist {
     pub name: String,
@@ -295,17 +244,6 @@ pub struct SpotifyArtist {
     pub id: String,
 }
 
-impl Display for SpotifyArtist {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let lines = [
-            format!("Name: {}", self.name),
-            format!("Genres: {}", self.genres.join(", ")),
-            format!("ID: {}", self.id),
-        ];
-        write!(f, "{}", lines.join("\n"))
-    }
-}
-
 #[derive(Debug, PartialEq)]
 pub enum PlayingState {
     Playing,
@@ -314,7 +252,11 @@ pub enum PlayingState {
 
 impl From<bool> for PlayingState {
     fn from(value: bool) -> Self {
-        return if value { Self::Playing } else { Self::Paused };
+        if value {
+            Self::Playing
+        } else {
+            Self::Paused
+        }
     }
 }
 
@@ -335,19 +277,6 @@ pub struct SpotifyTrack {
     pub id: String,
 }
 
-impl Display for SpotifyTrack {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let artist_names: Vec<&str> = self.artists.iter().map(|artist| artist.name.as_str()).collect();
-        let lines = [
-            format!("Name: {}", self.name),
-            format!("Album: {} (ID = {})", self.album.name, self.album.id),
-            format!("Artist(s): {}", artist_names.join(", ")),
-            format!("ID: {}", self.id),
-        ];
-        write!(f, "{}", lines.join("\n"))
-    }
-}
-
 #[derive(Deserialize)]
 pub struct SpotifyContext {
     pub uri: String,
@@ -357,51 +286,86 @@ pub struct SpotifyContext {
     pub context_type: String,
 }
 
-impl Display for SpotifyContext {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let lines = [
-            format!("URI: {}", self.uri),
-            format!("Href: {}", self.href),
-            format!("External URLs: {:?}", self.external_urls),
-            format!("Context Type: {}", self.context_type),
-        ];
-        write!(f, "{}", lines.join("\n"))
-    }
-}
-
 #[derive(Deserialize)]
 pub struct SpotifySimplifiedAlbum {
     pub name: String,
     pub album_type: String, // TODO: Convert to Enum
-    pub total_tracks: u8,
+    pub total_tracks: u32,
     pub artists: Vec<SpotifySimplifiedArtist>,
     pub release_date: String,
     pub release_date_precision: String,
     pub id: String,
 }
 
-impl Display for SpotifySimplifiedAlbum {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let artist_names: Vec<&str> = self.artists.iter().map(|artist| artist.name.as_str()).collect();
-        let lines = [
-            format!("Name: {}", self.name),
-            format!("Album: {}", self.album_type),
-            format!("Length: {} Tracks", self.total_tracks),
-            format!("Artist(s): {}", artist_names.join(", ")),
-            format!("Release Date: {}", self.release_date),
-            format!("ID: {}", self.id),
-        ];
-        write!(f, "{}", lines.join("\n"))
+// Conversions to the music types -----------------------------
+impl From<SpotifyTrack> for Track {
+    fn from(track: SpotifyTrack) -> Self {
+        Self {
+            name: track.name,
+            album: track.album.into(),
+            artists: track.artists.into_iter().map(Into::into).collect(),
+            id: track.id,
+        }
     }
 }
 
-// Serde help functions
-// TODO: ORGANIZE
-fn deserialize_vec_skip_null<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
-where
-    D: Deserializer<'de>,
-    T: Deserialize<'de>,
-{
-    let opt_vec: Option<Vec<Option<T>>> = Deserialize::deserialize(deserializer)?;
-    Ok(opt_vec.unwrap_or_default().into_iter().flatten().collect())
+impl From<SpotifySimplifiedAlbum> for Album {
+    fn from(album: SpotifySimplifiedAlbum) -> Self {
+        Self {
+            name: album.name,
+            album_type: album.album_type,
+            total_tracks: album.total_tracks,
+            artists: album.artists.into_iter().map(Into::into).collect(),
+            release_date: album.release_date,
+            release_date_precision: album.release_date_precision,
+            id: album.id,
+        }
+    }
+}
+
+impl From<SpotifySimplifiedArtist> for ArtistName {
+    fn from(artist: SpotifySimplifiedArtist) -> Self {
+        Self { name: artist.name }
+    }
+}
+
+impl From<SpotifyArtist> for Artist {
+    fn from(artist: SpotifyArtist) -> Self {
+        Self {
+            name: artist.name,
+            genres: artist.genres,
+            id: artist.id,
+        }
+    }
+}
+
+impl From<SpotifySimplifiedPlaylist> for Playlist {
+    fn from(playlist: SpotifySimplifiedPlaylist) -> Self {
+        Self {
+            name: playlist.name,
+            description: playlist.description,
+            id: playlist.id,
+            is_public: playlist.is_public,
+        }
+    }
+}
+
+impl From<SpotifyContext> for Context {
+    fn from(context: SpotifyContext) -> Self {
+        Self {
+            uri: context.uri,
+            href: context.href,
+            external_urls: context.external_urls,
+            context_type: context.context_type,
+        }
+    }
+}
+
+impl From<CurrentTrack> for NowPlaying {
+    fn from(current: CurrentTrack) -> Self {
+        Self {
+            track: current.item.map(Into::into),
+            context: current.context.map(Into::into),
+        }
+    }
 }
@@ -2,34 +2,44 @@ use crate::{
     modules::music::Token,
     utils::{
         cache::SpotifyCache,
+        config::SpotifyConfig,
         http::{ExtractFromResposneExt, ResponseError, ValidateResponseExt},
-        server::{read_localhost_request, TcpServerError},
-        AuthClient, Cache,
+        oauth::{random_string, PkceChallenge},
+        server::{CallbackServer, Request, TcpServerError},
+        AuthClient,
     },
 };
 use base64::{engine::general_purpose::STANDARD as base64_engine, Engine as _};
 use reqwest::{
-    blocking::RequestBuilder,
+    blocking::{Client, RequestBuilder},
     header::{AUTHORIZATION, CONTENT_TYPE},
 };
 use std::{
+    cell::RefCell,
     collections::HashMap,
+    fmt::Display,
+    io,
+    sync::mpsc,
+    thread,
     time::{Duration, SystemTime},
 };
 use thiserror::Error;
 use url::Url;
 
-const AUTH_ENDPOINT: &str = "https://accounts.spotify.com";
-const REDIRECT_PORT: u32 = 8888;
+const STATE_LENGTH: usize = 16;
+const CALLBACK_PATH: &str = "/callback";
 const API_SCOPE: &str = "user-read-playback-state user-modify-playback-state user-library-modify user-top-read";
 
 pub struct SpotifyAuthClient {
-    token: Token,
+    // In a cell since the token may be refreshed mid-command, when the API rejects it
+    token: RefCell<Token>,
+    config: SpotifyConfig,
+    client: Client,
 }
 
 impl AuthClient for SpotifyAuthClient {
     fn add_auth(&self, request: RequestBuilder) -> RequestBuilder {
-        request.header(AUTHORIZATION, self.token.as_auth())
+        request.header(AUTHORIZATION, self.token.borrow().as_auth())
     }
 }
 
@@ -45,104 +55,214 @@ pub enum AuthError {
 pub enum InitialAuthError {
     #[error("Failed to create a valid callback URL, this is usually a problem in the code: {0}")]
     FailedCallbackUrlCreation(url::ParseError),
-    #[error("Failed to open the Spotify authorization window: {0}")]
-    FailedToOpenAuthWindow(opener::OpenError),
+    #[error("Failed to start the local server for the redirect: {0}")]
+    FailedToStartCallbackServer(TcpServerError),
     #[error("Failed to read the the redirect: {0}")]
     FailedToReadRedirect(TcpServerError),
+    #[error("Spotify did not authorize the app, reason: {0}")]
+    AuthorizationRefused(String),
     #[error("Code param not found in redirect, given params: {0:?}")]
     CodeNotFoundInRedirect(HashMap<String, String>),
     #[error("Failed to get a token: {0}")]
     FailedToGetToken(ResponseError),
 }
 
+// NOTE: Without a client secret the PKCE flow is used, see `initial_auth` and `token_request`
 impl SpotifyAuthClient {
-    pub fn new(cache: &mut Cache, client_id: &str, client_secret: &str) -> Result<Self, AuthError> {
-        let token = Self::auth(cache, client_id, client_secret)?;
-        cache.modules.spotify = Some(SpotifyCache { token: token.clone() });
-        Ok(Self { token })
+    pub fn new(cache: &SpotifyCache, config: &SpotifyConfig, client: Client) -> Result<Self, AuthError> {
+        let token = Self::auth(cache, config, &client)?;
+        Ok(Self {
+            token: RefCell::new(token),
+            config: config.clone(),
+            client,
+        })
     }
 
-    pub fn remove_auth_from_cache(cache: &mut Cache) {
-        cache.modules.spotify = None
+    /// Gets a new token even if the current one looks valid, for when the API rejects it (revoked early or a skewed clock)
+    pub fn refresh(&self) -> Result<(), ResponseError> {
+        let token = Self::refresh_token(&self.token.borrow(), &self.config, &self.client)?;
+        self.token.replace(token);
+        Ok(())
+    }
+
+    /// Stores the current token, which may have been refreshed since the client was created
+    pub fn save_to_cache(&self, cache: &mut SpotifyCache) {
+        cache.set_token(&self.config.profile, Some(self.token.borrow().clone()));
     }
 
-    pub fn add_auth_to_cache(cache: &mut Cache, client_id: &str, client_secret: &str) -> Result<(), InitialAuthError> {
-        let token = Self::initial_auth(client_id, client_secret)?;
-        cache.modules.spotify = Some(SpotifyCache { token: token.clone() });
+    /// Only removes the token of the config's profile
+    pub fn remove_auth_from_cache(cache: &mut SpotifyCache, config: &SpotifyConfig) {
+        cache.set_token(&config.profile, None)
+    }
+
+    /// Without a browser (or if opening one fails) the authorization URL is printed to stderr, and the redirect can be either
+    /// received by the local server or pasted to stdin, which is useful on remote machines
+    pub fn add_auth_to_cache(cache: &mut SpotifyCache, config: &SpotifyConfig, client: &Client, open_browser: bool) -> Result<(), InitialAuthError> {
+        let browser = open_browser.then_some(|url: &Url| opener::open_browser(url.as_str()));
+        Self::add_auth_with_browser(cache, config, client, browser)
+    }
+
+    /// Like `add_auth_to_cache`, with `browser` opening the authorization URL instead of the system's browser
+    pub(crate) fn add_auth_with_browser<E: Display>(
+        cache: &mut SpotifyCache,
+        config: &SpotifyConfig,
+        client: &Client,
+        browser: Option<impl FnOnce(&Url) -> Result<(), E>>,
+    ) -> Result<(), InitialAuthError> {
+        let token = Self::initial_auth(config, client, browser)?;
+        cache.set_token(&config.profile, Some(token));
         Ok(())
     }
 
-    fn auth(cache: &Cache, client_id: &str, client_secret: &str) -> Result<Token, AuthError> {
-        match Self::get_token_from_cache(cache) {
-            Some(token) if token.is_valid() => Ok(token.clone()),
-            Some(token) => Self::refresh_token(token, client_id, client_secret).map_err(AuthError::FailedTokenRefresh),
+    fn auth(cache: &SpotifyCache, config: &SpotifyConfig, client: &Client) -> Result<Token, AuthError> {
+        match cache.get_token(&config.profile) {
+            Some(token) if token.is_valid(Duration::from_secs(config.refresh_margin_secs)) => Ok(token.clone()),
+            Some(token) => Self::refresh_token(token, config, client).map_err(AuthError::FailedTokenRefresh),
             None => Err(AuthError::NeedsInitialAuth),
         }
     }
 
-    fn get_token_from_cache(cache: &Cache) -> Option<&Token> {
-        Some(&cache.modules.spotify.as_ref()?.token)
+    fn refresh_token(prev_token: &Token, config: &SpotifyConfig, client: &Client) -> Result<Token, ResponseError> {
+        let form = [("grant_type", "refresh_token"), ("refresh_token", prev_token.refresh_token.as_str())];
+        let response = Self::token_request(config, client, &form).send();
+        Ok(response.validate()?.extract::<RefreshTokenFromApi>()?.into_token(prev_token))
     }
 
-    fn refresh_token(prev_token: &Token, client_id: &str, client_secret: &str) -> Result<Token, ResponseError> {
-        let encoded_auth = base64_engine.encode(format!("{}:{}", client_id, client_secret));
-        let response = reqwest::blocking::Client::new()
-            .post(format!("{}/api/token", AUTH_ENDPOINT))
-            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
-            .header(AUTHORIZATION, format!("Basic {}", encoded_auth))
-            .form(&[("grant_type", "refresh_token"), ("refresh_token", prev_token.refresh_token.as_str())])
-            .send();
-        Ok(response.validate()?.extract::<RefreshTokenFromApi>()?.to_token(prev_token))
-    }
+    fn initial_auth<E: Display>(
+        config: &SpotifyConfig,
+        client: &Client,
+        browser: Option<impl FnOnce(&Url) -> Result<(), E>>,
+    ) -> Result<Token, InitialAuthError> {
+        let pkce = config.client_secret.is_none().then(PkceChallenge::generate);
+        let state = random_string(STATE_LENGTH);
+        let server = Self::callback_server(config, state.clone())?;
+        let redirect_uri = format!("http://localhost:{}{}", server.port(), CALLBACK_PATH);
+        let auth_url = Self::auth_url(config, &redirect_uri, &state, pkce.as_ref())?;
 
-    fn initial_auth(client_id: &str, client_secret: &str) -> Result<Token, InitialAuthError> {
-        Self::open_auth_window(client_id)?;
-        let code = Self::get_code_from_callback()?;
-        let token = Self::get_token(code, client_id, client_secret).map_err(InitialAuthError::FailedToGetToken)?;
+        let opened_browser = browser.is_some_and(|open| {
+            open(&auth_url)
+                .map_err(|err| eprintln!("WARNING: Failed to open a browser, falling back to manual authorization: {}", err))
+                .is_ok()
+        });
+        let code = if opened_browser {
+            server.serve().map_err(InitialAuthError::FailedToReadRedirect)??
+        } else {
+            Self::get_code_manually(server, &auth_url, state)?
+        };
+        let token = Self::get_token(config, client, code, &redirect_uri, pkce.as_ref()).map_err(InitialAuthError::FailedToGetToken)?;
         Ok(token)
     }
 
-    fn open_auth_window(client_id: &str) -> Result<(), InitialAuthError> {
-        let redirect_uri = format!("http://localhost:{}/callback", REDIRECT_PORT);
-        let uri = Url::parse_with_params(
-            format!("{}/{}", AUTH_ENDPOINT, "authorize").as_str(),
-            &[
-                ("response_type", "code"),
-                ("client_id", client_id),
-                ("redirect_uri", redirect_uri.as_str()),
-                ("scope", API_SCOPE),
-            ],
-        )
-        .map_err(InitialAuthError::FailedCallbackUrlCreation)?;
+    fn callback_server(config: &SpotifyConfig, state: String) -> Result<CallbackServer<String, InitialAuthError>, InitialAuthError> {
+        let server = CallbackServer::bind(&config.redirect_ports).map_err(InitialAuthError::FailedToStartCallbackServer)?;
+        Ok(server
+            .timeout(Duration::from_secs(config.auth_timeout_secs))
+            .route(CALLBACK_PATH, move |callback| {
+                // Anything can reach the port, a stray or forged request mustn't end the wait for the real redirect
+                let from_this_login = Self::has_state(callback, &state);
+                if !from_this_login {
+                    eprintln!("WARNING: Ignoring a request to the callback with another state, it may not be from Spotify");
+                }
+                from_this_login.then(|| Self::get_code_from_callback(callback))
+            }))
+    }
+
+    fn auth_url(config: &SpotifyConfig, redirect_uri: &str, state: &str, pkce: Option<&PkceChallenge>) -> Result<Url, InitialAuthError> {
+        let mut params = vec![
+            ("response_type", "code"),
+            ("client_id", config.client_id.as_str()),
+            ("redirect_uri", redirect_uri),
+            ("scope", API_SCOPE),
+            ("state", state),
+        ];
+        if let Some(pkce) = pkce {
+            params.extend([("code_challenge_method", pkce.method()), ("code_challenge", pkce.challenge.as_str())]);
+        }
+        Url::parse_with_params(format!("{}/{}", config.accounts_url, "authorize").as_str(), &params)
+            .map_err(InitialAuthError::FailedCallbackUrlCreation)
+    }
+
+    /// Waits for whichever comes first, the redirect reaching the local server or the redirect URL being pasted to stdin
+    fn get_code_manually(server: CallbackServer<String, InitialAuthError>, auth_url: &Url, state: String) -> Result<String, InitialAuthError> {
+        eprintln!("Open this URL in a browser to authorize Aerial:\n\n{}\n", auth_url);
+        eprintln!("If the browser can't reach this machine after authorizing, paste the full URL it was redirected to here:");
+
+        let (sender, receiver) = mpsc::channel();
+        let server_sender = sender.clone();
+        thread::spawn(move || {
+            let _ = server_sender.send(server.serve().map_err(InitialAuthError::FailedToReadRedirect).and_then(|res| res));
+        });
+        thread::spawn(move || {
+            for line in io::stdin().lines().map_while(Result::ok).filter(|line| !line.trim().is_empty()) {
+                match Url::parse(line.trim()) {
+                    Ok(url) if url.path() == CALLBACK_PATH && Self::has_state(&Request::from(&url), &state) => {
+                        let _ = sender.send(Self::get_code_from_callback(&Request::from(&url)));
+                        return;
+                    }
+                    Ok(url) if url.path() == CALLBACK_PATH => {
+                        eprintln!("That redirect is from another authorization (its state doesn't match), paste the one from this authorization:")
+                    }
+                    Ok(url) => eprintln!(
+                        "That isn't the redirect URL (its path is `{}`), paste the full URL from the browser's address bar:",
+                        url.path()
+                    ),
+                    Err(err) => eprintln!("That isn't a valid URL ({}), paste the full URL from the browser's address bar:", err),
+                }
+            }
+        });
+        // The server thread always sends a result eventually, since it times out
+        receiver.recv().expect("The redirect server thread stopped without a result")
+    }
 
-        opener::open(uri.to_string()).map_err(InitialAuthError::FailedToOpenAuthWindow)
+    /// Whether the redirect is for the authorization that sent `state`
+    fn has_state(callback: &Request, state: &str) -> bool {
+        callback.params.get("state").map(String::as_str) == Some(state)
     }
 
-    fn get_code_from_callback() -> Result<String, InitialAuthError> {
-        let callback = read_localhost_request(REDIRECT_PORT).map_err(InitialAuthError::FailedToReadRedirect)?;
-        Ok(callback
-            .params
+    fn get_code_from_callback(callback: &Request) -> Result<String, InitialAuthError> {
+        let params = &callback.params;
+        if let Some(error) = params.get("error") {
+            return Err(InitialAuthError::AuthorizationRefused(error.clone()));
+        }
+        params
             .get("code")
-            .ok_or(InitialAuthError::CodeNotFoundInRedirect(callback.params.clone()))?
-            .to_string())
-    }
-
-    fn get_token(code: String, client_id: &str, client_secret: &str) -> Result<Token, ResponseError> {
-        let encoded_auth = base64_engine.encode(format!("{}:{}", client_id, client_secret));
-        let redirect_uri = format!("http://localhost:{}/callback", REDIRECT_PORT);
-        let response = reqwest::blocking::Client::new()
-            .post(format!("{}/api/token", AUTH_ENDPOINT))
-            .header(AUTHORIZATION, format!("Basic {}", encoded_auth))
-            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
-            .form(&[
-                ("grant_type", "authorization_code"),
-                ("code", code.as_str()),
-                ("redirect_uri", redirect_uri.as_str()),
-            ])
-            .send();
+            .cloned()
+            .ok_or(InitialAuthError::CodeNotFoundInRedirect(params.clone()))
+    }
+
+    fn get_token(
+        config: &SpotifyConfig,
+        client: &Client,
+        code: String,
+        redirect_uri: &str,
+        pkce: Option<&PkceChallenge>,
+    ) -> Result<Token, ResponseError> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code.as_str()),
+            ("redirect_uri", redirect_uri),
+        ];
+        if let Some(pkce) = pkce {
+            form.push(("code_verifier", pkce.verifier.as_str()));
+        }
+        let response = Self::token_request(config, client, &form).send();
 
         Ok(response.validate()?.extract::<TokenFromApi>()?.into())
     }
+
+    /// A request to the token endpoint, authenticated by the client secret if there is one and by the client id alone (PKCE) if not
+    fn token_request(config: &SpotifyConfig, client: &Client, form: &[(&str, &str)]) -> RequestBuilder {
+        let request = client
+            .post(format!("{}/api/token", config.accounts_url))
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded");
+        match &config.client_secret {
+            Some(client_secret) => {
+                let encoded_auth = base64_engine.encode(format!("{}:{}", config.client_id, client_secret));
+                request.header(AUTHORIZATION, format!("Basic {}", encoded_auth)).form(form)
+            }
+            None => request.form(&[form, &[("client_id", config.client_id.as_str())]].concat()),
+        }
+    }
 }
 
 #[derive(serde::Deserialize)]
@@ -174,7 +294,7 @@ impl From<TokenFromApi> for Token {
 }
 
 impl RefreshTokenFromApi {
-    fn to_token(self, prev_token: &Token) -> Token {
+    fn into_token(self, prev_token: &Token) -> Token {
         Token {
             access_token: self.access_token,
             token_type: self.token_type,
@@ -1,29 +1,104 @@
-use super::{spotify_api_handler::SpotifyApiHandler, spotify_api_spec::{
-    GetCurrentTrack, GetPlaybackState, GotoNextTrack, GotoPrevTrack, Pause, Play, PlaybackState, PlayingState, Resume, SaveTracks, Search,
-    SetShuffle, ShuffleState, SpotifySearchType, SpotifyTimeRange, GetTopTracks
-}};
 use super::spotify_auth::{AuthError, InitialAuthError};
-use crate::modules::music::spotify::spotify_api_spec::spotify_search_results_to_string;
-use crate::modules::music::MusicClient;
-use crate::utils::{api_handler::ApiHandler, config::SpotifyConfig, http::ResponseError, Cache};
+use super::{
+    spotify_api_handler::SpotifyApiHandler,
+    spotify_api_spec::{
+        GetCurrentTrack, GetPlaybackState, GetTopTracks, GotoNextTrack, GotoPrevTrack, PageRange, Pause, Play, PlaybackState, PlayingState, Resume,
+        SaveTracks, Search, SetShuffle, ShuffleState, SpotifySearchResults, SpotifySearchType, SpotifyTimeRange,
+    },
+};
+use crate::modules::music::{
+    types::{NowPlaying, SearchResults, Track},
+    MusicClient, PageArgs,
+};
+use crate::utils::{
+    api_handler::ApiHandler,
+    cache::SpotifyCache,
+    config::SpotifyConfig,
+    http::{ResponseError, ResponseValidationError},
+    paging::Paging,
+};
+use reqwest::{blocking::Client, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
 use thiserror::Error;
 
+const MAX_PAGE_LIMIT: u32 = 50;
+
 pub struct SpotifyClient {
     pub api_handler: SpotifyApiHandler,
+    max_paged_items: usize,
 }
 
 #[derive(Error, Debug)]
 pub enum SpotifyError {
-    #[error("There is no actively playing Spotify device")]
+    #[error("There is no actively playing Spotify device, start playing on one of the user's devices and try again")]
     NoActiveDevice,
     #[error("Action can't be performed when music is {0}")]
     UnwantedPlayingState(PlayingState),
+    #[error("This action requires Spotify Premium, with a free account it can only be done from the Spotify app")]
+    PremiumRequired,
+    #[error("The Spotify authorization doesn't allow this action, run `music auth` to authorize again with the needed permissions")]
+    InsufficientScope,
+    #[error("Spotify rejected the authentication ({0}), run `music auth` to authenticate again")]
+    Unauthorized(String),
+    #[error("Spotify is limiting the rate of requests, wait a minute and try again")]
+    RateLimited,
+    #[error("Spotify couldn't find what was requested ({0}), check the given ids, e.g. by searching with `music search`")]
+    NotFound(String),
     #[error("Theres an error in the API request: {0}")]
     ApiRequestError(ResponseError),
     #[error("Failed to initial authentication: {0}")]
     FailedInitialAuth(InitialAuthError),
 }
 
+/// The body Spotify sends with failed requests: `{"error": {"status": 404, "message": "...", "reason": "..."}}`
+#[derive(Deserialize)]
+struct ApiErrorBody {
+    error: ApiErrorObject,
+}
+
+#[derive(Deserialize)]
+struct ApiErrorObject {
+    message: String,
+    reason: Option<String>,
+}
+
+impl SpotifyError {
+    /// A stable name for the error in the serialized output formats
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SpotifyError::NoActiveDevice => "no_active_device",
+            SpotifyError::UnwantedPlayingState(_) => "unwanted_playing_state",
+            SpotifyError::PremiumRequired => "premium_required",
+            SpotifyError::InsufficientScope => "insufficient_scope",
+            SpotifyError::Unauthorized(_) => "unauthorized",
+            SpotifyError::RateLimited => "rate_limited",
+            SpotifyError::NotFound(_) => "not_found",
+            SpotifyError::ApiRequestError(_) => "api_request_error",
+            SpotifyError::FailedInitialAuth(_) => "failed_initial_auth",
+        }
+    }
+}
+
+impl From<ResponseError> for SpotifyError {
+    fn from(err: ResponseError) -> Self {
+        let ResponseError::InvalidResposne(ResponseValidationError::BadStatusCode(status, body)) = &err else {
+            return Self::ApiRequestError(err);
+        };
+        let error = serde_json::from_str::<ApiErrorBody>(body).ok().map(|body| body.error);
+        let message = error.as_ref().map(|error| error.message.clone()).unwrap_or_default();
+
+        match (*status, error.as_ref().and_then(|error| error.reason.as_deref())) {
+            (_, Some("NO_ACTIVE_DEVICE")) => Self::NoActiveDevice,
+            (_, Some("PREMIUM_REQUIRED")) => Self::PremiumRequired,
+            (_, Some("RATE_LIMITED")) | (StatusCode::TOO_MANY_REQUESTS, _) => Self::RateLimited,
+            (StatusCode::FORBIDDEN, _) if message.to_lowercase().contains("scope") => Self::InsufficientScope,
+            (StatusCode::UNAUTHORIZED, _) => Self::Unauthorized(message),
+            (StatusCode::NOT_FOUND, _) => Self::NotFound(message),
+            _ => Self::ApiRequestError(err),
+        }
+    }
+}
+
 impl MusicClient for SpotifyClient {
     type Error = SpotifyError;
 
@@ -36,13 +111,13 @@ impl MusicClient for SpotifyClient {
 
     fn pause(&self) -> Result<(), SpotifyError> {
         self.verify_playing_state(PlayingState::Playing)?;
-        self.api_handler.make_request(&Pause).map_err(SpotifyError::ApiRequestError)?;
+        self.api_handler.make_request(&Pause)?;
         Ok(())
     }
 
     fn resume(&self) -> Result<(), Self::Error> {
         self.verify_playing_state(PlayingState::Paused)?;
-        self.api_handler.make_request(&Resume).map_err(SpotifyError::ApiRequestError)?;
+        self.api_handler.make_request(&Resume)?;
         Ok(())
     }
 
@@ -57,88 +132,99 @@ impl MusicClient for SpotifyClient {
             }
         };
 
-        self.api_handler.make_request(&request).map_err(SpotifyError::ApiRequestError)?;
+        self.api_handler.make_request(&request)?;
         Ok(())
     }
 
     fn goto_next_track(&self) -> Result<(), Self::Error> {
         self.verify_active_device()?;
-        self.api_handler.make_request(&GotoNextTrack).map_err(SpotifyError::ApiRequestError)?;
+        self.api_handler.make_request(&GotoNextTrack)?;
         Ok(())
     }
 
     fn goto_prev_track(&self) -> Result<(), Self::Error> {
         self.verify_active_device()?;
-        self.api_handler.make_request(&GotoPrevTrack).map_err(SpotifyError::ApiRequestError)?;
+        self.api_handler.make_request(&GotoPrevTrack)?;
         Ok(())
     }
 
     fn set_shuffle_state(&self, state: &ShuffleState) -> Result<(), Self::Error> {
         let shuffle = state.into_bool();
         self.verify_active_device()?;
-        self.api_handler
-            .make_request(&SetShuffle { state: shuffle })
-            .map_err(SpotifyError::ApiRequestError)?;
+        self.api_handler.make_request(&SetShuffle { state: shuffle })?;
         Ok(())
     }
 
-    fn search(&self, query: String, search_type: SpotifySearchType) -> Result<(), Self::Error> {
-        let search_results = self
-            .api_handler
-            .make_request(&Search {
-                query: query.clone(),
-                search_type: vec![search_type.clone()],
-            })
-            .map_err(SpotifyError::ApiRequestError)?;
-        println!(
-            "{}",
-            match search_type {
-                SpotifySearchType::Track => spotify_search_results_to_string(search_results.tracks.map(|i| i.items)),
-                SpotifySearchType::Album => spotify_search_results_to_string(search_results.albums.map(|i| i.items)),
-                SpotifySearchType::Artist => spotify_search_results_to_string(search_results.artists.map(|i| i.items)),
-                SpotifySearchType::Playlist => spotify_search_results_to_string(search_results.playlists.map(|i| i.items)),
+    fn search(&self, query: String, search_type: SpotifySearchType, page: PageArgs) -> Result<SearchResults, Self::Error> {
+        let search_results = self.api_handler.make_request(&Search {
+            query: query.clone(),
+            search_type: vec![search_type.clone()],
+            page: Self::page_range(&page),
+        })?;
+        // Pages after the first one are wrapped the same way, so each type's paging is taken out of them the same way
+        Ok(match search_type {
+            SpotifySearchType::Track => SearchResults::Tracks(self.page_items(search_results.tracks, &page, |r: SpotifySearchResults| r.tracks)?),
+            SpotifySearchType::Album => SearchResults::Albums(self.page_items(search_results.albums, &page, |r: SpotifySearchResults| r.albums)?),
+            SpotifySearchType::Artist => SearchResults::Artists(self.page_items(search_results.artists, &page, |r: SpotifySearchResults| r.artists)?),
+            SpotifySearchType::Playlist => {
+                SearchResults::Playlists(self.page_items(search_results.playlists, &page, |r: SpotifySearchResults| r.playlists)?)
             }
-        );
-        Ok(())
+        })
     }
 
     fn save_tracks(&self, ids: Vec<String>) -> Result<(), Self::Error> {
-        self.api_handler
-            .make_request(&SaveTracks { ids })
-            .map_err(SpotifyError::ApiRequestError)?;
+        self.api_handler.make_request(&SaveTracks { ids })?;
         Ok(())
     }
 
-
-    fn get_top_tracks(&self, time_range: SpotifyTimeRange) -> Result<(), Self::Error> {
-        let top_tracks = self.api_handler
-            .make_request(&GetTopTracks { time_range })
-            .map_err(SpotifyError::ApiRequestError)?;
-        println!("{}", top_tracks.items.iter().map(|track| track.to_string()).collect::<Vec<String>>().join("\n\n"));
-        Ok(())
+    fn get_top_tracks(&self, time_range: SpotifyTimeRange, page: PageArgs) -> Result<Vec<Track>, Self::Error> {
+        let top_tracks = self.api_handler.make_request(&GetTopTracks {
+            time_range,
+            page: Self::page_range(&page),
+        })?;
+        self.page_items(Some(top_tracks), &page, Some)
     }
 
-    fn print_current_track(&self) -> Result<(), Self::Error> {
+    fn get_current_track(&self) -> Result<NowPlaying, Self::Error> {
         self.verify_active_device()?;
-        let curr_track = self.api_handler.make_request(&GetCurrentTrack).map_err(SpotifyError::ApiRequestError)?;
-        println!("{}", curr_track);
-        Ok(())
+        let curr_track = self.api_handler.make_request(&GetCurrentTrack)?;
+        Ok(curr_track.into())
     }
 }
 
 impl SpotifyClient {
-    pub fn new(config: &SpotifyConfig, cache: &mut Cache) -> Result<Self, AuthError> {
+    /// `client` is used for all requests, including the token's, build it with `config.http.build_client()` or share an existing one
+    pub fn new(config: &SpotifyConfig, cache: &SpotifyCache, client: Client) -> Result<Self, AuthError> {
         Ok(Self {
-            api_handler: SpotifyApiHandler::new(config, cache)?,
+            api_handler: SpotifyApiHandler::new(config, cache, client)?,
+            max_paged_items: config.max_paged_items,
         })
     }
 
+    fn page_range(page: &PageArgs) -> PageRange {
+        // When getting everything the biggest pages mean the least requests
+        let limit = page.limit.or(page.all.then_some(MAX_PAGE_LIMIT));
+        PageRange { limit, offset: page.offset }
+    }
+
+    /// The items of the first page, and of the pages after it if all of them were asked for.
+    /// A missing page (like a search type Spotify didn't return) has no items
+    fn page_items<R: DeserializeOwned, T, U: From<T>>(
+        &self,
+        first_page: Option<Paging<T>>,
+        page: &PageArgs,
+        get_page: impl Fn(R) -> Option<Paging<T>>,
+    ) -> Result<Vec<U>, SpotifyError> {
+        let items = match first_page {
+            Some(first_page) if page.all => self.api_handler.collect_pages(first_page, self.max_paged_items, get_page)?,
+            Some(first_page) => first_page.items,
+            None => Vec::new(),
+        };
+        Ok(items.into_iter().map(U::from).collect())
+    }
+
     fn get_playing_state(&self) -> Result<Option<PlayingState>, SpotifyError> {
-        let state: Option<PlaybackState> = self
-            .api_handler
-            .make_request(&GetPlaybackState)
-            .map_err(SpotifyError::ApiRequestError)?
-            .into();
+        let state: Option<PlaybackState> = self.api_handler.make_request(&GetPlaybackState)?.into();
         Ok(state.map(|st| PlayingState::from(st.is_playing)))
     }
 
@@ -0,0 +1,781 @@
+use super::{
+    spotify::{
+        mock_server::{catalog_tracks, MockFailure, MockSpotify, MockState, MOCK_CLIENT_ID},
+        spotify_api_handler::SpotifyApiHandler,
+        spotify_api_spec::{GotoNextTrack, PlayingState, SpotifySearchType, SpotifyTimeRange, SpotifyTrack},
+        spotify_client::{SpotifyClient, SpotifyError},
+    },
+    types::{SearchResults, Track},
+    AuthError, InitialAuthError, Music, MusicArgs, MusicClient, MusicError, MusicOutput, PageArgs, SpotifyAuthClient,
+};
+use crate::{
+    cli::AerialUtilsArgs,
+    modules::{Module, ModuleRegistry},
+    utils::{
+        api_handler::ApiHandler,
+        api_spec::{NoBody, NoResponse},
+        cache::SpotifyCache,
+        config::{SpotifyConfig, DEFAULT_PROFILE},
+        http::{ResponseError, ResponseValidationError},
+        output::{Done, ErrorOutput, List, OutputFormat},
+        retry::RetryPolicy,
+        ApiRequest, ApiRequestSpec,
+    },
+};
+use clap::FromArgMatches;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::{
+    net::TcpListener,
+    thread,
+    time::{Duration, Instant},
+};
+use url::Url;
+
+fn output(config: SpotifyConfig, cache: &mut SpotifyCache, args: &[&str]) -> Result<MusicOutput, MusicError> {
+    let argv = ["aerial-utils", "music"].iter().chain(args);
+    let args = AerialUtilsArgs::try_parse_with(&ModuleRegistry::default(), argv).unwrap();
+    let Some((_, matches)) = args.module else {
+        panic!("Expected a music command");
+    };
+    Music::run(MusicArgs::from_arg_matches(&matches).unwrap(), &config, cache)
+}
+
+fn run(config: SpotifyConfig, cache: &mut SpotifyCache, args: &[&str]) -> Result<(), MusicError> {
+    output(config, cache, args).map(|_| ())
+}
+
+fn run_on(mock: &MockSpotify, args: &[&str]) -> Result<(), MusicError> {
+    run(mock.config(), &mut mock.cache(), args)
+}
+
+/// The command's result as printed with `--output json`
+fn json_on(mock: &MockSpotify, args: &[&str]) -> Value {
+    let output = output(mock.config(), &mut mock.cache(), args).unwrap();
+    serde_json::from_str(&OutputFormat::Json.render(&output)).unwrap()
+}
+
+fn names(results: &Value) -> Vec<&str> {
+    results.as_array().unwrap().iter().filter_map(|result| result["name"].as_str()).collect()
+}
+
+fn playing() -> MockState {
+    MockState {
+        is_playing: true,
+        ..Default::default()
+    }
+}
+
+fn no_device() -> MockState {
+    MockState {
+        active_device: false,
+        ..Default::default()
+    }
+}
+
+fn assert_no_active_device(result: Result<(), MusicError>) {
+    assert!(
+        matches!(result, Err(MusicError::FailedAction(SpotifyError::NoActiveDevice))),
+        "Expected NoActiveDevice, got {:?}",
+        result
+    );
+}
+
+#[test]
+fn toggle_pauses_playing_music() {
+    let mock = MockSpotify::start_with(playing());
+    run_on(&mock, &["toggle"]).unwrap();
+    assert!(!mock.state().is_playing);
+}
+
+#[test]
+fn toggle_resumes_paused_music() {
+    let mock = MockSpotify::start();
+    run_on(&mock, &["toggle"]).unwrap();
+    assert!(mock.state().is_playing);
+}
+
+#[test]
+fn toggle_without_device_fails() {
+    let mock = MockSpotify::start_with(no_device());
+    assert_no_active_device(run_on(&mock, &["toggle"]));
+}
+
+#[test]
+fn pause_playing_music() {
+    let mock = MockSpotify::start_with(playing());
+    run_on(&mock, &["pause"]).unwrap();
+    assert!(!mock.state().is_playing);
+}
+
+#[test]
+fn pause_paused_music_fails() {
+    let mock = MockSpotify::start();
+    let result = run_on(&mock, &["pause"]);
+    assert!(matches!(
+        result,
+        Err(MusicError::FailedAction(SpotifyError::UnwantedPlayingState(PlayingState::Paused)))
+    ));
+    assert!(!mock.received("PUT /v1/me/player/pause"));
+}
+
+#[test]
+fn pause_without_device_fails() {
+    let mock = MockSpotify::start_with(no_device());
+    assert_no_active_device(run_on(&mock, &["pause"]));
+}
+
+#[test]
+fn resume_paused_music() {
+    let mock = MockSpotify::start();
+    run_on(&mock, &["resume"]).unwrap();
+    assert!(mock.state().is_playing);
+}
+
+#[test]
+fn resume_playing_music_fails() {
+    let mock = MockSpotify::start_with(playing());
+    let result = run_on(&mock, &["resume"]);
+    assert!(matches!(
+        result,
+        Err(MusicError::FailedAction(SpotifyError::UnwantedPlayingState(PlayingState::Playing)))
+    ));
+}
+
+#[test]
+fn play_track() {
+    let mock = MockSpotify::start();
+    run_on(&mock, &["play", "--track", "mock-track-2"]).unwrap();
+    let state = mock.state();
+    assert!(state.is_playing);
+    assert_eq!(state.current_track, 1);
+}
+
+#[test]
+fn play_track_in_context() {
+    let mock = MockSpotify::start();
+    run_on(&mock, &["play", "--context", "album:mock-album", "--track", "mock-track-3"]).unwrap();
+    let state = mock.state();
+    assert_eq!(state.context.as_deref(), Some("spotify:album:mock-album"));
+    assert_eq!(state.current_track, 2);
+}
+
+#[test]
+fn play_without_track_or_context_is_rejected() {
+    assert!(AerialUtilsArgs::try_parse_with(&ModuleRegistry::default(), ["aerial-utils", "music", "play"]).is_err());
+}
+
+#[test]
+fn play_without_device_fails() {
+    let mock = MockSpotify::start_with(no_device());
+    assert_no_active_device(run_on(&mock, &["play", "--track", "mock-track-1"]));
+}
+
+#[test]
+fn next_track() {
+    let mock = MockSpotify::start();
+    run_on(&mock, &["next"]).unwrap();
+    assert_eq!(mock.state().current_track, 1);
+}
+
+#[test]
+fn next_track_without_device_fails() {
+    let mock = MockSpotify::start_with(no_device());
+    assert_no_active_device(run_on(&mock, &["next"]));
+}
+
+#[test]
+fn prev_track() {
+    let mock = MockSpotify::start_with(MockState {
+        current_track: 2,
+        ..Default::default()
+    });
+    run_on(&mock, &["prev"]).unwrap();
+    assert_eq!(mock.state().current_track, 1);
+}
+
+#[test]
+fn prev_track_without_device_fails() {
+    let mock = MockSpotify::start_with(no_device());
+    assert_no_active_device(run_on(&mock, &["prev"]));
+}
+
+#[test]
+fn search_every_type() {
+    let mock = MockSpotify::start();
+    let expected = [
+        ("track", vec!["Mock Song", "Another Mock Song", "Last Mock Song"]),
+        ("album", vec!["Mock Album"]),
+        ("artist", vec!["Mock Artist"]),
+        ("playlist", vec!["Mock Playlist"]),
+    ];
+    for (search_type, expected) in expected {
+        let results = json_on(&mock, &["search", "mock", "--search-type", search_type]);
+        assert_eq!(names(&results), expected, "searching for {}s", search_type);
+    }
+    assert_eq!(json_on(&mock, &["search", "last"])[0]["id"], "mock-track-3");
+    assert_eq!(json_on(&mock, &["search", "nothing like it"]), json!([]));
+    assert_eq!(mock.state().params[0].get("type").map(String::as_str), Some("track"));
+}
+
+#[test]
+fn set_shuffle() {
+    let mock = MockSpotify::start();
+    run_on(&mock, &["set-shuffle", "on"]).unwrap();
+    assert!(mock.state().shuffle);
+    run_on(&mock, &["set-shuffle", "off"]).unwrap();
+    assert!(!mock.state().shuffle);
+}
+
+#[test]
+fn set_shuffle_without_device_fails() {
+    let mock = MockSpotify::start_with(no_device());
+    assert_no_active_device(run_on(&mock, &["set-shuffle", "on"]));
+}
+
+#[test]
+fn save_tracks() {
+    let mock = MockSpotify::start();
+    run_on(&mock, &["save", "mock-track-1", "mock-track-3"]).unwrap();
+    assert_eq!(mock.state().saved_tracks, ["mock-track-1", "mock-track-3"]);
+}
+
+#[test]
+fn top_tracks() {
+    let mock = MockSpotify::start();
+    let tracks = json_on(&mock, &["top-tracks", "--time-range", "short"]);
+    assert_eq!(names(&tracks), ["Mock Song", "Another Mock Song", "Last Mock Song"]);
+    assert_eq!(tracks[1]["artists"][0]["name"], "Mock Artist");
+    assert_eq!(mock.state().params[0].get("time_range").map(String::as_str), Some("short_term"));
+}
+
+fn client(mock: &MockSpotify) -> SpotifyClient {
+    let config = mock.config();
+    SpotifyClient::new(&config, &mock.cache(), config.http.build_client().unwrap()).unwrap()
+}
+
+const FIRST_PAGE: PageArgs = PageArgs {
+    limit: None,
+    offset: None,
+    all: false,
+};
+
+#[test]
+fn top_tracks_are_returned() {
+    let mock = MockSpotify::start();
+    let tracks = client(&mock).get_top_tracks(SpotifyTimeRange::Short, FIRST_PAGE).unwrap();
+    let names: Vec<&str> = tracks.iter().map(|track| track.name.as_str()).collect();
+    assert_eq!(names, ["Mock Song", "Another Mock Song", "Last Mock Song"]);
+    assert_eq!(tracks[0].album.id, "mock-album");
+}
+
+#[test]
+fn search_results_are_returned_by_type() {
+    let mock = MockSpotify::start();
+    let client = client(&mock);
+    let SearchResults::Tracks(tracks) = client.search("another".into(), SpotifySearchType::Track, FIRST_PAGE).unwrap() else {
+        panic!("Expected tracks");
+    };
+    assert_eq!(tracks.len(), 1);
+    assert_eq!(tracks[0].id, "mock-track-2");
+    let SearchResults::Artists(artists) = client.search("mock".into(), SpotifySearchType::Artist, FIRST_PAGE).unwrap() else {
+        panic!("Expected artists");
+    };
+    assert_eq!(artists[0].genres, ["mock rock"]);
+}
+
+#[test]
+fn current_track_is_returned_with_its_context() {
+    let mock = MockSpotify::start_with(MockState {
+        current_track: 2,
+        context: Some("spotify:playlist:mock-playlist".into()),
+        ..playing()
+    });
+    let now_playing = client(&mock).get_current_track().unwrap();
+    assert_eq!(now_playing.track.map(|track| track.id).as_deref(), Some("mock-track-3"));
+    assert_eq!(now_playing.context.map(|context| context.context_type).as_deref(), Some("playlist"));
+}
+
+fn requested_offsets(mock: &MockSpotify) -> Vec<String> {
+    mock.state()
+        .params
+        .iter()
+        .map(|params| params.get("offset").cloned().unwrap_or_default())
+        .collect()
+}
+
+#[test]
+fn limit_and_offset_are_sent() {
+    let mock = MockSpotify::start();
+    let tracks = json_on(&mock, &["top-tracks", "--limit", "1", "--offset", "2"]);
+    assert_eq!(names(&tracks), ["Last Mock Song"]);
+    let params = &mock.state().params[0];
+    assert_eq!(params.get("limit").map(String::as_str), Some("1"));
+    assert_eq!(params.get("offset").map(String::as_str), Some("2"));
+}
+
+#[test]
+fn limit_over_50_is_rejected() {
+    assert!(AerialUtilsArgs::try_parse_with(&ModuleRegistry::default(), ["aerial-utils", "music", "top-tracks", "--limit", "51"]).is_err());
+}
+
+#[test]
+fn all_follows_next_links() {
+    let mock = MockSpotify::start();
+    let tracks = json_on(&mock, &["top-tracks", "--all", "--limit", "1"]);
+    assert_eq!(names(&tracks), ["Mock Song", "Another Mock Song", "Last Mock Song"]);
+    assert_eq!(requested_offsets(&mock), ["", "1", "2"]);
+}
+
+#[test]
+fn all_follows_next_links_of_search_results() {
+    let mock = MockSpotify::start();
+    let tracks = json_on(&mock, &["search", "mock", "--all", "--limit", "2"]);
+    assert_eq!(names(&tracks), ["Mock Song", "Another Mock Song", "Last Mock Song"]);
+    assert_eq!(mock.state().requests, ["GET /v1/search", "GET /v1/search"]);
+    assert_eq!(requested_offsets(&mock), ["", "2"]);
+    assert_eq!(mock.state().params[1].get("q").map(String::as_str), Some("mock"));
+}
+
+#[test]
+fn all_stops_at_max_paged_items() {
+    let mock = MockSpotify::start();
+    let config = SpotifyConfig {
+        max_paged_items: 2,
+        ..mock.config()
+    };
+    let MusicOutput::Tracks(tracks) = output(config, &mut mock.cache(), &["top-tracks", "--all", "--limit", "1"]).unwrap() else {
+        panic!("Expected tracks");
+    };
+    assert_eq!(tracks.0.len(), 2);
+    assert_eq!(requested_offsets(&mock), ["", "1"]);
+}
+
+#[test]
+fn current_track() {
+    let mock = MockSpotify::start_with(MockState {
+        current_track: 1,
+        context: Some("spotify:playlist:mock-playlist".into()),
+        ..playing()
+    });
+    let now_playing = json_on(&mock, &["curr-track"]);
+    assert_eq!(now_playing["track"]["name"], "Another Mock Song");
+    assert_eq!(now_playing["track"]["album"]["name"], "Mock Album");
+    assert_eq!(now_playing["context"]["uri"], "spotify:playlist:mock-playlist");
+    assert_eq!(now_playing["context"]["context_type"], "playlist");
+}
+
+#[test]
+fn current_track_without_device_fails() {
+    let mock = MockSpotify::start_with(no_device());
+    assert_no_active_device(run_on(&mock, &["curr-track"]));
+}
+
+#[test]
+fn auth_fails_when_no_redirect_port_is_free() {
+    let mock = MockSpotify::start();
+    let taken = TcpListener::bind("localhost:0").unwrap();
+    let config = SpotifyConfig {
+        redirect_ports: vec![taken.local_addr().unwrap().port()],
+        ..mock.config()
+    };
+    let result = run(config, &mut SpotifyCache::default(), &["auth", "--no-browser"]);
+    assert!(matches!(
+        result,
+        Err(MusicError::FailedAction(SpotifyError::FailedInitialAuth(
+            InitialAuthError::FailedToStartCallbackServer(_)
+        )))
+    ));
+}
+
+/// A browser that follows the authorization URL, the mock redirects it straight back to the local server with a code
+fn follow_redirects(url: &Url) -> Result<(), String> {
+    let url = url.clone();
+    // The redirect can only be answered once `initial_auth` serves it, after the browser was opened
+    thread::spawn(move || reqwest::blocking::get(url).and_then(|response| response.error_for_status()).unwrap());
+    Ok(())
+}
+
+fn authorize(config: &SpotifyConfig, cache: &mut SpotifyCache) -> Result<(), InitialAuthError> {
+    authorize_with(config, cache, follow_redirects)
+}
+
+fn authorize_with(config: &SpotifyConfig, cache: &mut SpotifyCache, browser: fn(&Url) -> Result<(), String>) -> Result<(), InitialAuthError> {
+    let redirect_port = TcpListener::bind("localhost:0").unwrap().local_addr().unwrap().port();
+    let config = SpotifyConfig {
+        redirect_ports: vec![redirect_port],
+        // A browser that failed doesn't make the test wait for the default timeout
+        auth_timeout_secs: 10,
+        ..config.clone()
+    };
+    SpotifyAuthClient::add_auth_with_browser(cache, &config, &config.http.build_client().unwrap(), Some(browser))
+}
+
+#[test]
+fn auth_round_trip_with_pkce() {
+    let mock = MockSpotify::start();
+    let mut cache = SpotifyCache::default();
+    authorize(&mock.config(), &mut cache).unwrap();
+
+    let state = mock.state();
+    assert_eq!(state.requests, ["GET /authorize", "POST /api/token"]);
+    assert_eq!(state.params[0].get("client_id").map(String::as_str), Some(MOCK_CLIENT_ID));
+    assert_eq!(state.params[0].get("code_challenge_method").map(String::as_str), Some("S256"));
+    assert!(state.code_challenge.is_some());
+    let token = cache.get_token(DEFAULT_PROFILE).unwrap();
+    assert_eq!(token.access_token, state.access_token);
+    assert!(token.is_valid(Duration::ZERO));
+    drop(state);
+
+    run(mock.config(), &mut cache, &["next"]).unwrap();
+    assert_eq!(mock.state().current_track, 1);
+}
+
+#[test]
+fn auth_round_trip_with_client_secret() {
+    let mock = MockSpotify::start();
+    let config = SpotifyConfig {
+        client_secret: Some("mock-secret".into()),
+        ..mock.config()
+    };
+    let mut cache = SpotifyCache::default();
+    authorize(&config, &mut cache).unwrap();
+    assert!(!mock.state().params[0].contains_key("code_challenge"));
+    assert_eq!(cache.get_token(DEFAULT_PROFILE).unwrap().access_token, mock.state().access_token);
+}
+
+#[test]
+fn auth_ignores_callbacks_with_another_state() {
+    fn forge_then_follow(url: &Url) -> Result<(), String> {
+        let redirect_uri = url.query_pairs().find(|(key, _)| key == "redirect_uri").unwrap().1.into_owned();
+        let url = url.clone();
+        thread::spawn(move || {
+            let forged = reqwest::blocking::get(format!("{}?state=forged&code=forged-code", redirect_uri)).unwrap();
+            assert_eq!(forged.status(), reqwest::StatusCode::BAD_REQUEST);
+            reqwest::blocking::get(url).and_then(|response| response.error_for_status()).unwrap();
+        });
+        Ok(())
+    }
+    let mock = MockSpotify::start();
+    let mut cache = SpotifyCache::default();
+    authorize_with(&mock.config(), &mut cache, forge_then_follow).unwrap();
+    assert_eq!(cache.get_token(DEFAULT_PROFILE).unwrap().access_token, mock.state().access_token);
+}
+
+#[test]
+fn unauth_removes_token() {
+    let mock = MockSpotify::start();
+    let mut cache = mock.cache();
+    run(mock.config(), &mut cache, &["unauth"]).unwrap();
+    assert!(cache.get_token(DEFAULT_PROFILE).is_none());
+}
+
+#[test]
+fn commands_need_auth() {
+    let mock = MockSpotify::start();
+    let result = run(mock.config(), &mut SpotifyCache::default(), &["toggle"]);
+    assert!(matches!(result, Err(MusicError::FailedAuth(AuthError::NeedsInitialAuth))));
+}
+
+fn with_work_profile(mock: &MockSpotify) -> SpotifyConfig {
+    let mut config = mock.config();
+    config.profiles.insert("work".into(), Default::default());
+    config
+}
+
+#[test]
+fn profiles_have_their_own_tokens() {
+    let mock = MockSpotify::start();
+    let mut cache = mock.cache();
+    let result = run(with_work_profile(&mock), &mut cache, &["--profile", "work", "next"]);
+    assert!(matches!(result, Err(MusicError::FailedAuth(AuthError::NeedsInitialAuth))));
+
+    let token = cache.get_token(DEFAULT_PROFILE).cloned();
+    cache.set_token("work", token);
+    run(with_work_profile(&mock), &mut cache, &["next", "--profile", "work"]).unwrap();
+    run(with_work_profile(&mock), &mut cache, &["unauth", "--profile", "work"]).unwrap();
+    assert!(cache.get_token("work").is_none());
+    assert!(cache.get_token(DEFAULT_PROFILE).is_some());
+}
+
+#[test]
+fn default_profile_is_used_without_profile_flag() {
+    let mock = MockSpotify::start();
+    let mut config = with_work_profile(&mock);
+    config.default_profile = "work".into();
+    let result = run(config, &mut mock.cache(), &["next"]);
+    assert!(matches!(result, Err(MusicError::FailedAuth(AuthError::NeedsInitialAuth))));
+}
+
+#[test]
+fn unknown_profile_is_rejected() {
+    let mock = MockSpotify::start();
+    let result = run_on(&mock, &["--profile", "missing", "next"]);
+    assert!(matches!(result, Err(MusicError::UnknownProfile(name)) if name == "missing"));
+    assert!(mock.state().requests.is_empty());
+}
+
+#[test]
+fn profiles_are_listed_without_auth() {
+    let mock = MockSpotify::start();
+    run(with_work_profile(&mock), &mut SpotifyCache::default(), &["profiles", "list"]).unwrap();
+    assert!(mock.state().requests.is_empty());
+}
+
+#[test]
+fn expired_token_is_refreshed() {
+    let mock = MockSpotify::start();
+    let mut cache = mock.expired_cache();
+    run(mock.config(), &mut cache, &["next"]).unwrap();
+    assert!(mock.received("POST /api/token"));
+    let token = cache.get_token(DEFAULT_PROFILE).unwrap();
+    assert_eq!(token.access_token, mock.state().access_token);
+    assert!(token.is_valid(Duration::ZERO));
+}
+
+#[test]
+fn requests_of_a_command_share_a_connection() {
+    let mock = MockSpotify::start();
+    run(mock.config(), &mut mock.expired_cache(), &["toggle"]).unwrap();
+    let state = mock.state();
+    assert_eq!(state.requests.len(), 4, "{:?}", state.requests);
+    assert_eq!(state.connections.len(), 1);
+}
+
+#[test]
+fn invalid_proxy_fails_before_any_request() {
+    let mock = MockSpotify::start();
+    let mut config = mock.config();
+    config.http.proxy = Some("not a proxy url".into());
+    let result = run(config, &mut mock.cache(), &["toggle"]);
+    assert!(matches!(result, Err(MusicError::FailedToCreateHttpClient(_))), "got {:?}", result);
+    assert!(mock.state().requests.is_empty());
+}
+
+fn failing(failures: Vec<MockFailure>) -> MockSpotify {
+    MockSpotify::start_with(MockState {
+        failures,
+        ..Default::default()
+    })
+}
+
+fn fast_retries(mock: &MockSpotify) -> SpotifyConfig {
+    SpotifyConfig {
+        retry: RetryPolicy {
+            base_delay_ms: 1,
+            max_delay_ms: 1,
+            ..Default::default()
+        },
+        ..mock.config()
+    }
+}
+
+#[test]
+fn rate_limited_request_waits_for_retry_after() {
+    let mock = failing(vec![MockFailure {
+        retry_after: Some(1),
+        ..MockFailure::new("GET /v1/me/top/tracks", 429)
+    }]);
+    let start = Instant::now();
+    run(fast_retries(&mock), &mut mock.cache(), &["top-tracks"]).unwrap();
+    assert!(start.elapsed() >= Duration::from_secs(1));
+    assert_eq!(mock.state().requests.len(), 2);
+}
+
+#[test]
+fn rate_limited_request_with_long_retry_after_fails() {
+    let mock = failing(vec![MockFailure {
+        retry_after: Some(3600),
+        ..MockFailure::new("GET /v1/me/top/tracks", 429)
+    }]);
+    let result = run(fast_retries(&mock), &mut mock.cache(), &["top-tracks"]);
+    assert!(matches!(result, Err(MusicError::FailedAction(SpotifyError::RateLimited))));
+    assert_eq!(mock.state().requests.len(), 1);
+}
+
+#[test]
+fn server_errors_are_retried() {
+    let mock = failing(vec![
+        MockFailure::new("GET /v1/me/player", 503),
+        MockFailure::new("GET /v1/me/player", 500),
+    ]);
+    run(fast_retries(&mock), &mut mock.cache(), &["resume"]).unwrap();
+    assert!(mock.state().is_playing);
+}
+
+#[test]
+fn non_idempotent_requests_are_not_retried() {
+    let mock = failing(vec![MockFailure::new("POST /v1/me/player/next", 503)]);
+    let result = run(fast_retries(&mock), &mut mock.cache(), &["next"]);
+    assert!(matches!(
+        result,
+        Err(MusicError::FailedAction(SpotifyError::ApiRequestError(ResponseError::InvalidResposne(
+            ResponseValidationError::BadStatusCode(StatusCode::SERVICE_UNAVAILABLE, _)
+        ))))
+    ));
+    assert_eq!(mock.state().current_track, 0);
+}
+
+/// Skipping a track marked as safe to send twice, which it isn't
+struct RetrySafeNext;
+
+impl ApiRequestSpec for RetrySafeNext {
+    type Resposne = NoResponse;
+    type Body = NoBody;
+
+    fn request(&self) -> ApiRequest<NoBody> {
+        GotoNextTrack.request()
+    }
+
+    fn retry_safe(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn requests_marked_retry_safe_are_retried() {
+    let mock = failing(vec![
+        MockFailure::new("POST /v1/me/player/next", 503),
+        MockFailure::new("POST /v1/me/player/next", 503),
+    ]);
+    let config = fast_retries(&mock);
+    let handler = SpotifyApiHandler::new(&config, &mock.cache(), config.http.build_client().unwrap()).unwrap();
+    assert!(handler.make_request(&GotoNextTrack).is_err());
+    handler.make_request(&RetrySafeNext).unwrap();
+    assert_eq!(
+        mock.state().requests,
+        ["POST /v1/me/player/next", "POST /v1/me/player/next", "POST /v1/me/player/next"]
+    );
+    assert_eq!(mock.state().current_track, 1);
+}
+
+#[test]
+fn retries_stop_after_max_retries() {
+    let mock = failing((0..4).map(|_| MockFailure::new("GET /v1/me/top/tracks", 502)).collect());
+    assert!(run(fast_retries(&mock), &mut mock.cache(), &["top-tracks"]).is_err());
+    assert_eq!(mock.state().requests.len(), 4);
+}
+
+#[test]
+fn rejected_token_is_refreshed_and_saved() {
+    let mock = MockSpotify::start();
+    let mut cache = mock.cache();
+    cache.token.as_mut().unwrap().access_token = "revoked-token".into();
+    run(mock.config(), &mut cache, &["next"]).unwrap();
+    assert_eq!(mock.state().current_track, 1);
+    assert_eq!(cache.get_token(DEFAULT_PROFILE).unwrap().access_token, mock.state().access_token);
+}
+
+#[test]
+fn rejected_token_is_only_refreshed_once() {
+    let mock = failing(vec![
+        MockFailure::new("GET /v1/me/top/tracks", 401),
+        MockFailure::new("GET /v1/me/top/tracks", 401),
+    ]);
+    assert!(run(mock.config(), &mut mock.cache(), &["top-tracks"]).is_err());
+    assert_eq!(mock.state().tokens_issued, 1);
+}
+
+#[test]
+fn token_about_to_expire_is_refreshed() {
+    let mock = MockSpotify::start();
+    let mut cache = mock.cache_with_token_age(Duration::from_secs(3600 - 30));
+    run(mock.config(), &mut cache, &["top-tracks"]).unwrap();
+    assert_eq!(mock.state().requests, ["POST /api/token", "GET /v1/me/top/tracks"]);
+}
+
+fn failing_with(request: &str, status: u16, message: &str, reason: Option<&str>) -> MockSpotify {
+    MockSpotify::start_with(MockState {
+        failures: vec![MockFailure {
+            message: message.into(),
+            reason: reason.map(String::from),
+            ..MockFailure::new(request, status)
+        }],
+        ..playing()
+    })
+}
+
+#[test]
+fn premium_required_error_is_decoded() {
+    let mock = failing_with(
+        "PUT /v1/me/player/pause",
+        403,
+        "Player command failed: Premium required",
+        Some("PREMIUM_REQUIRED"),
+    );
+    let result = run_on(&mock, &["pause"]);
+    assert!(matches!(result, Err(MusicError::FailedAction(SpotifyError::PremiumRequired))));
+}
+
+#[test]
+fn device_lost_mid_command_is_decoded() {
+    let mock = failing_with(
+        "PUT /v1/me/player/pause",
+        404,
+        "Player command failed: No active device found",
+        Some("NO_ACTIVE_DEVICE"),
+    );
+    assert_no_active_device(run_on(&mock, &["pause"]));
+}
+
+#[test]
+fn insufficient_scope_error_is_decoded() {
+    let mock = failing_with("PUT /v1/me/tracks", 403, "Insufficient client scope", None);
+    let result = run_on(&mock, &["save", "mock-track-1"]);
+    assert!(matches!(result, Err(MusicError::FailedAction(SpotifyError::InsufficientScope))));
+}
+
+#[test]
+fn not_found_error_is_decoded() {
+    let mock = failing_with("PUT /v1/me/player/play", 404, "Non existing id: 'spotify:track:nope'", None);
+    let result = run_on(&mock, &["play", "--track", "nope"]);
+    assert!(matches!(result, Err(MusicError::FailedAction(SpotifyError::NotFound(message))) if message.contains("nope")));
+}
+
+#[test]
+fn output_format_is_accepted_before_and_after_the_command() {
+    /// The result rendered in the format given with the full command line
+    fn rendered_on(mock: &MockSpotify, argv: &[&str]) -> String {
+        let args = AerialUtilsArgs::try_parse_with(&ModuleRegistry::default(), ["aerial-utils"].iter().chain(argv)).unwrap();
+        let (_, matches) = args.module.expect("Expected a music command");
+        let output = Music::run(MusicArgs::from_arg_matches(&matches).unwrap(), &mock.config(), &mut mock.cache()).unwrap();
+        args.output.render(&output)
+    }
+    let mock = MockSpotify::start();
+    let before = rendered_on(&mock, &["--output", "json", "music", "top-tracks"]);
+    assert_eq!(
+        names(&serde_json::from_str(&before).unwrap()),
+        ["Mock Song", "Another Mock Song", "Last Mock Song"]
+    );
+    assert_eq!(rendered_on(&mock, &["music", "top-tracks", "--output", "json"]), before);
+    assert_eq!(rendered_on(&mock, &["--output", "yaml", "music", "next"]), "status: ok");
+    assert_eq!(rendered_on(&mock, &["music", "next", "--output", "yaml"]), "status: ok");
+    assert!(AerialUtilsArgs::try_parse_with(&ModuleRegistry::default(), ["aerial-utils", "--output", "xml", "music", "next"]).is_err());
+}
+
+#[test]
+fn tracks_are_serialized() {
+    let track: Track = serde_json::from_value::<SpotifyTrack>(catalog_tracks().remove(0)).unwrap().into();
+    let json: Value = serde_json::from_str(&OutputFormat::Json.render(&List(vec![track]))).unwrap();
+    assert_eq!(json[0]["name"], "Mock Song");
+    assert_eq!(json[0]["id"], "mock-track-1");
+    assert_eq!(json[0]["album"]["id"], "mock-album");
+    assert_eq!(json[0]["artists"][0]["name"], "Mock Artist");
+}
+
+#[test]
+fn actions_print_a_status_only_when_serialized() {
+    assert_eq!(OutputFormat::Text.render(&Done), "");
+    assert_eq!(OutputFormat::Yaml.render(&Done), "status: ok");
+    let json: Value = serde_json::from_str(&OutputFormat::Json.render(&Done)).unwrap();
+    assert_eq!(json["status"], "ok");
+}
+
+#[test]
+fn errors_are_serialized_with_their_kind() {
+    let mock = MockSpotify::start_with(no_device());
+    let err = run_on(&mock, &["toggle"]).unwrap_err();
+    let json: Value = serde_json::from_str(&OutputFormat::Json.render(&ErrorOutput::new(err.kind(), &err))).unwrap();
+    assert_eq!(json["error"]["kind"], "no_active_device");
+    assert_eq!(json["error"]["message"], err.to_string());
+}
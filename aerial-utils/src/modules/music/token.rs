@@ -15,8 +15,9 @@ impl Token {
         format!("{}  {}", self.token_type, self.access_token)
     }
 
-    pub fn is_valid(&self) -> bool {
+    /// Whether the token will still be valid after the given margin
+    pub fn is_valid(&self, margin: Duration) -> bool {
         let experation_time = self.time_set + self.expires_in;
-        return experation_time > SystemTime::now();
+        experation_time > SystemTime::now() + margin
     }
 }
@@ -0,0 +1,70 @@
+//! The music values returned by a `MusicClient`, independent of the service they came from
+
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Track {
+    pub name: String,
+    pub album: Album,
+    pub artists: Vec<ArtistName>,
+    pub id: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Album {
+    pub name: String,
+    pub album_type: String,
+    pub total_tracks: u32,
+    pub artists: Vec<ArtistName>,
+    pub release_date: String,
+    /// How much of `release_date` is known: `year`, `month` or `day`
+    pub release_date_precision: String,
+    pub id: String,
+}
+
+/// An artist as credited on a track or album
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ArtistName {
+    pub name: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Artist {
+    pub name: String,
+    pub genres: Vec<String>,
+    pub id: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Playlist {
+    pub name: String,
+    pub description: String,
+    pub id: String,
+    pub is_public: bool,
+}
+
+/// What the music is playing from, like an album or a playlist
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Context {
+    pub uri: String,
+    pub href: String,
+    pub external_urls: HashMap<String, String>,
+    pub context_type: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct NowPlaying {
+    pub track: Option<Track>,
+    pub context: Option<Context>,
+}
+
+/// The results of a search, of the type that was searched for
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum SearchResults {
+    Tracks(Vec<Track>),
+    Albums(Vec<Album>),
+    Artists(Vec<Artist>),
+    Playlists(Vec<Playlist>),
+}
@@ -0,0 +1,255 @@
+//! Modules outside of the crate, written in any language: `aerial-utils-<name>` executables on the `PATH` are run as
+//! `aerial-utils <name> ...`, like git finds its subcommands.
+//!
+//! A plugin describes its commands when run with `--aerial-spec`, by printing a JSON [`PluginSpec`]:
+//!
+//! ```json
+//! {
+//!   "about": "Weather forecasts",
+//!   "commands": [
+//!     {
+//!       "name": "forecast",
+//!       "about": "The forecast for the next days",
+//!       "args": [
+//!         { "name": "city", "help": "The city to get the forecast of", "required": true, "positional": true },
+//!         { "name": "days", "help": "How many days to get", "possible_values": ["1", "3", "7"] },
+//!         { "name": "metric", "help": "Use metric units", "flag": true }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! The commands are only listed by `command-specs` and `mcp`, any arguments are passed on to the plugin as they were given.
+//! Tool calls through `mcp` are given as `--<name> <value>` for each value (`--<name>` alone for set flags), followed by
+//! `--` and the positional args (if there are any). The plugin's stdout is the result then, and it gets no stdin.
+//! The plugin gets the format to print in and the files in use through the `AERIAL_OUTPUT`, `AERIAL_CONFIG` and
+//! `AERIAL_CACHE` environment variables, its config can be under `[modules.<name>]`.
+
+use crate::{
+    cli::Paths,
+    utils::{
+        output::OutputFormat,
+        paths::{CACHE_FILE, CONFIG_FILE},
+    },
+};
+use clap::{Arg, ArgAction, Command, ValueEnum};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    env,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+    process::{self, ExitStatus},
+};
+use thiserror::Error;
+
+pub const PLUGIN_PREFIX: &str = "aerial-utils-";
+pub const SPEC_FLAG: &str = "--aerial-spec";
+const OUTPUT_ENV: &str = "AERIAL_OUTPUT";
+const ARGS_ID: &str = "args";
+
+#[derive(Error, Debug)]
+pub enum PluginError {
+    #[error("Failed to run `{0}`: {1}")]
+    FailedToRun(PathBuf, io::Error),
+    #[error("`{0}` exited with {1}")]
+    Failed(PathBuf, ExitStatus),
+    #[error("Invalid `--aerial-spec` output from `{0}`: {1}")]
+    InvalidSpec(PathBuf, serde_json::Error),
+}
+
+impl PluginError {
+    /// A stable name for the error in the serialized output formats
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PluginError::FailedToRun(..) => "plugin_not_runnable",
+            PluginError::Failed(..) => "plugin_failed",
+            PluginError::InvalidSpec(..) => "invalid_plugin_spec",
+        }
+    }
+}
+
+/// An `aerial-utils-<name>` executable
+pub struct Plugin {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// What a plugin prints when run with `--aerial-spec`
+#[derive(Deserialize, Debug)]
+pub struct PluginSpec {
+    pub about: Option<String>,
+    pub commands: Vec<PluginCommand>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PluginCommand {
+    pub name: String,
+    pub about: Option<String>,
+    #[serde(default)]
+    pub args: Vec<PluginArg>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PluginArg {
+    pub name: String,
+    pub help: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+    /// Given by its position instead of as `--<name> <value>`, in the order of the positional args
+    #[serde(default)]
+    pub positional: bool,
+    /// Given as `--<name>` without a value
+    #[serde(default)]
+    pub flag: bool,
+    #[serde(default)]
+    pub possible_values: Vec<String>,
+}
+
+impl Plugin {
+    /// The plugins in the directories of the `PATH` variable, the first one of each name like with commands
+    pub fn discover(path_var: Option<OsString>) -> Vec<Plugin> {
+        let mut names = HashSet::new();
+        let mut plugins = Vec::new();
+        for dir in path_var.iter().flat_map(env::split_paths) {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            let mut found: Vec<Plugin> = entries.flatten().filter_map(|entry| Self::from_path(entry.path())).collect();
+            found.sort_by(|a, b| a.name.cmp(&b.name));
+            plugins.extend(found.into_iter().filter(|plugin| names.insert(plugin.name.clone())));
+        }
+        plugins
+    }
+
+    fn from_path(path: PathBuf) -> Option<Plugin> {
+        let file_name = path.file_name()?.to_str()?;
+        let name = file_name.strip_prefix(PLUGIN_PREFIX)?;
+        let name = executable_name(&path, name)?.to_string();
+        (!name.is_empty()).then_some(Plugin { name, path })
+    }
+
+    /// Runs the plugin with `--aerial-spec` to get its commands
+    pub fn spec(&self) -> Result<PluginSpec, PluginError> {
+        let output = process::Command::new(&self.path)
+            .arg(SPEC_FLAG)
+            .stderr(process::Stdio::inherit())
+            .output()
+            .map_err(|err| PluginError::FailedToRun(self.path.clone(), err))?;
+        if !output.status.success() {
+            return Err(PluginError::Failed(self.path.clone(), output.status));
+        }
+        serde_json::from_slice(&output.stdout).map_err(|err| PluginError::InvalidSpec(self.path.clone(), err))
+    }
+
+    /// Runs the plugin with the arguments given after its name, it prints its results itself
+    pub fn run(&self, matches: &clap::ArgMatches, paths: &Paths, output: OutputFormat) -> Result<(), PluginError> {
+        let status = self
+            .process(matches, paths, output)
+            .status()
+            .map_err(|err| PluginError::FailedToRun(self.path.clone(), err))?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(PluginError::Failed(self.path.clone(), status)),
+        }
+    }
+
+    /// Runs the plugin like `run`, but returns what it printed. It gets no stdin, so it can't wait for input
+    pub fn output(&self, matches: &clap::ArgMatches, paths: &Paths, output: OutputFormat) -> Result<String, PluginError> {
+        let result = self
+            .process(matches, paths, output)
+            .stdin(process::Stdio::null())
+            .stderr(process::Stdio::inherit())
+            .output()
+            .map_err(|err| PluginError::FailedToRun(self.path.clone(), err))?;
+        match result.status.success() {
+            true => Ok(String::from_utf8_lossy(&result.stdout).trim_end().into()),
+            false => Err(PluginError::Failed(self.path.clone(), result.status)),
+        }
+    }
+
+    fn process(&self, matches: &clap::ArgMatches, paths: &Paths, output: OutputFormat) -> process::Command {
+        let args = matches.get_many::<OsString>(ARGS_ID).into_iter().flatten();
+        let format = output.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default();
+        let mut process = process::Command::new(&self.path);
+        process
+            .args(args)
+            .env(OUTPUT_ENV, format)
+            .env(CONFIG_FILE.env_var(), &paths.config.path)
+            .env(CACHE_FILE.env_var(), &paths.cache.path);
+        process
+    }
+
+    /// The subcommand that takes any arguments to pass them on, parsing them is up to the plugin
+    pub fn command(&self) -> Command {
+        Command::new(self.name.clone())
+            .about(format!("External module (`{}`)", self.path.display()))
+            .disable_help_flag(true)
+            .arg(
+                Arg::new(ARGS_ID)
+                    .num_args(0..)
+                    .trailing_var_arg(true)
+                    .allow_hyphen_values(true)
+                    .value_parser(clap::value_parser!(OsString)),
+            )
+    }
+}
+
+impl PluginSpec {
+    /// The plugin's commands as a clap command, to be described like the commands of the native modules
+    pub fn command(&self, name: &str) -> Command {
+        let subcommands = self.commands.iter().map(PluginCommand::command);
+        with_about(Command::new(name.to_string()), &self.about).subcommands(subcommands)
+    }
+}
+
+impl PluginCommand {
+    fn command(&self) -> Command {
+        let mut index = 0;
+        let args = self.args.iter().map(|plugin_arg| {
+            let mut arg = Arg::new(plugin_arg.name.clone()).required(plugin_arg.required);
+            if let Some(help) = &plugin_arg.help {
+                arg = arg.help(help.clone());
+            }
+            if plugin_arg.positional {
+                index += 1;
+                arg = arg.index(index);
+            } else {
+                arg = arg.long(plugin_arg.name.clone());
+            }
+            if plugin_arg.flag {
+                arg = arg.action(ArgAction::SetTrue);
+            }
+            if !plugin_arg.possible_values.is_empty() {
+                arg = arg.value_parser(plugin_arg.possible_values.clone());
+            }
+            arg
+        });
+        with_about(Command::new(self.name.clone()), &self.about).args(args.collect::<Vec<_>>())
+    }
+}
+
+/// Without an about the command is described as having no description, instead of with an empty one
+fn with_about(command: Command, about: &Option<String>) -> Command {
+    match about {
+        Some(about) => command.about(about.clone()),
+        None => command,
+    }
+}
+
+/// The plugin's name without the executable's extension, `None` if the file isn't executable
+#[cfg(unix)]
+fn executable_name<'a>(path: &Path, name: &'a str) -> Option<&'a str> {
+    use std::os::unix::fs::PermissionsExt;
+    let metadata = fs::metadata(path).ok()?;
+    (metadata.is_file() && metadata.permissions().mode() & 0o111 != 0).then_some(name)
+}
+
+#[cfg(not(unix))]
+fn executable_name<'a>(path: &Path, name: &'a str) -> Option<&'a str> {
+    let extension = path.extension()?.to_str()?;
+    let is_executable = ["exe", "bat", "cmd"].iter().any(|ext| extension.eq_ignore_ascii_case(ext));
+    (path.is_file() && is_executable).then(|| &name[..name.len() - extension.len() - 1])
+}
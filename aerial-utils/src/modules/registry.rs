@@ -0,0 +1,144 @@
+//! The modules the CLI is made of, adding a module only takes registering it in `ModuleRegistry::default`
+
+use super::{plugin::Plugin, Module, Music};
+use crate::{
+    cli::{AerialUtilsArgs, AppError, Paths},
+    utils::{config::ConfigSection, output::OutputFormat, Cache, Config},
+};
+use clap::{ArgMatches, Args, Command, CommandFactory, FromArgMatches};
+use std::ffi::OsString;
+
+/// A registered module: its subcommand, the sections it uses and how its commands are run
+pub struct ModuleEntry {
+    pub name: String,
+    /// The config section, which can turn the module off
+    pub config_section: String,
+    kind: ModuleKind,
+}
+
+enum ModuleKind {
+    Native {
+        command: fn() -> Command,
+        /// Returns the command's result rendered in the output format
+        run: fn(&ArgMatches, &Config, &mut Cache, OutputFormat) -> Result<String, AppError>,
+    },
+    Plugin(Plugin),
+}
+
+pub struct ModuleRegistry {
+    modules: Vec<ModuleEntry>,
+}
+
+impl Default for ModuleRegistry {
+    /// All of Aerial's modules
+    fn default() -> Self {
+        Self::new().register::<Music>()
+    }
+}
+
+impl ModuleRegistry {
+    pub fn new() -> Self {
+        Self { modules: Vec::new() }
+    }
+
+    pub fn register<M: Module>(mut self) -> Self {
+        self.modules.push(ModuleEntry {
+            name: M::NAME.into(),
+            config_section: M::Config::NAME.into(),
+            kind: ModuleKind::Native {
+                command: || M::Args::augment_args(Command::new(M::NAME).about(M::ABOUT)),
+                run: run_module::<M>,
+            },
+        });
+        self
+    }
+
+    /// Adds the plugins in the `PATH` (see `plugin`), except those named like a module or a command of the CLI
+    pub fn with_plugins(self, path_var: Option<OsString>) -> Self {
+        Plugin::discover(path_var)
+            .into_iter()
+            .fold(self, |registry, plugin| registry.register_plugin(plugin))
+    }
+
+    pub fn register_plugin(mut self, plugin: Plugin) -> Self {
+        let cli = AerialUtilsArgs::command();
+        if self.get(&plugin.name).is_some() || cli.find_subcommand(&plugin.name).is_some() || plugin.name == "help" {
+            eprintln!(
+                "WARNING: Ignoring `{}`, a command named `{}` already exists",
+                plugin.path.display(),
+                plugin.name
+            );
+            return self;
+        }
+        self.modules.push(ModuleEntry {
+            name: plugin.name.clone(),
+            config_section: plugin.name.clone(),
+            kind: ModuleKind::Plugin(plugin),
+        });
+        self
+    }
+
+    pub fn modules(&self) -> impl Iterator<Item = &ModuleEntry> {
+        self.modules.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ModuleEntry> {
+        self.modules.iter().find(|module| module.name == name)
+    }
+
+    /// The modules the config doesn't turn off
+    pub fn enabled<'a>(&'a self, config: &'a Config) -> impl Iterator<Item = &'a ModuleEntry> {
+        self.modules().filter(|module| config.modules.is_enabled(&module.config_section))
+    }
+}
+
+impl ModuleEntry {
+    /// The subcommand the module is parsed with
+    pub fn command(&self) -> Command {
+        match &self.kind {
+            ModuleKind::Native { command, .. } => command(),
+            ModuleKind::Plugin(plugin) => plugin.command(),
+        }
+    }
+
+    /// The subcommand describing the module's commands, for plugins it comes from their spec, which may fail to be read
+    pub fn spec_command(&self) -> Result<Command, AppError> {
+        match &self.kind {
+            ModuleKind::Native { command, .. } => Ok(command()),
+            ModuleKind::Plugin(plugin) => Ok(plugin.spec().map_err(AppError::PluginError)?.command(&self.name)),
+        }
+    }
+
+    /// Runs the module's command in `matches` and prints its result, with the module's sections of the config and cache.
+    /// Plugins get the paths of the files instead, and read them themselves
+    pub fn run(&self, matches: &ArgMatches, config: &Config, cache: &mut Cache, paths: &Paths, output: OutputFormat) -> Result<(), AppError> {
+        match &self.kind {
+            ModuleKind::Native { run, .. } => {
+                OutputFormat::print_rendered(&run(matches, config, cache, output)?);
+                Ok(())
+            }
+            ModuleKind::Plugin(plugin) => plugin.run(matches, paths, output).map_err(AppError::PluginError),
+        }
+    }
+
+    /// Runs the command like `run`, but returns its result instead of printing it. Plugins don't get a stdin then
+    pub fn output(&self, matches: &ArgMatches, config: &Config, cache: &mut Cache, paths: &Paths, output: OutputFormat) -> Result<String, AppError> {
+        match &self.kind {
+            ModuleKind::Native { run, .. } => run(matches, config, cache, output),
+            ModuleKind::Plugin(plugin) => plugin.output(matches, paths, output).map_err(AppError::PluginError),
+        }
+    }
+}
+
+fn run_module<M: Module>(matches: &ArgMatches, config: &Config, cache: &mut Cache, output: OutputFormat) -> Result<String, AppError> {
+    // The matches come from the module's own command, so they should always fit its args. Exiting on an error would
+    // still take down the MCP server with it, so it's returned like any other
+    let args = M::Args::from_arg_matches(matches).map_err(AppError::InvalidArguments)?;
+    let module_config = config.modules.section::<M::Config>().map_err(AppError::ConfigError)?;
+    let module_config = module_config.ok_or(AppError::MissingConfig(M::Config::NAME))?;
+    let mut module_cache = cache.modules.section::<M::Cache>().map_err(AppError::CacheError)?;
+    let res = M::run(args, &module_config, &mut module_cache);
+    cache.modules.set_section(&module_cache).map_err(AppError::CacheError)?;
+    let result = res.map_err(|err| AppError::ModuleError(M::default().to_string(), M::error_kind(&err), Box::new(err)))?;
+    Ok(output.render(&result))
+}
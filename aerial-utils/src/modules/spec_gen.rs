@@ -1,110 +1,698 @@
-use crate::AerialUtilsArgs;
-use clap::{Command, CommandFactory};
+//! The commands of the CLI as tool specs for LLM assistants, so they can run them. The specs are built once as [`Tool`]s
+//! from the clap commands, and printed in the format of the assistant's provider
+
+use clap::{
+    error::{ContextKind, ContextValue, ErrorKind},
+    Arg, ArgAction, Command,
+};
 use serde::Serialize;
-use std::collections::HashMap;
+use serde_json::{json, Map, Value};
+use std::{
+    any::TypeId,
+    collections::{BTreeSet, HashMap},
+};
+use thiserror::Error;
 
 // TODO: Theoretically this whole thing should be a part of the build process
 
-pub fn print_subcommand_specs() {
-    let mut cmd = AerialUtilsArgs::command();
-    cmd.build();
-    print_chatgpt_subcommands(&cmd);
+/// The format the tool specs are printed in. Each is what goes in the `tools` of a request to the provider's API, except
+/// for the OpenAPI document and the schemas
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum SpecFormat {
+    /// OpenAI function tools
+    #[default]
+    Openai,
+    /// Anthropic tools
+    Anthropic,
+    /// Gemini function declarations, without the rules about which arguments go together since it doesn't support them
+    Gemini,
+    /// An OpenAPI document with a POST operation for each command, taking its arguments as the JSON body
+    Openapi,
+    /// The JSON Schema of each command's arguments, by the command's tool name
+    Jsonschema,
 }
 
-fn print_chatgpt_subcommands(cmd: &Command) {
-    let subcommands = get_chatgpt_subcommands(cmd, "");
-    println!("{}", serde_json::to_string_pretty(&subcommands).unwrap());
+/// Prints the specs of the CLI's subcommands, given the CLI with only the modules that should be listed
+pub fn print_subcommand_specs(cmd: Command, format: SpecFormat) {
+    println!("{}", serde_json::to_string_pretty(&format.render(&tools(cmd))).unwrap());
 }
 
-fn get_chatgpt_subcommands(cmd: &Command, prefix: &str) -> Vec<ChatGPTCommand> {
-    let children_prefix = match cmd.get_name() {
-        "aerial-utils" => "".into(),
-        _ => format!("{}{}_", prefix, cmd.get_name()),
-    };
-    let mut subcommands = Vec::new();
+/// The tools of the CLI's subcommands, one for each subcommand without subcommands of its own
+pub fn tools(mut cmd: Command) -> Vec<Tool> {
+    cmd.build();
+    // The CLI's own global args like `--output` are for whoever runs it, not part of the commands. Global args of a module
+    // (like `--profile`) are, they're only global so they can be given after the subcommand
+    let cli_args: Vec<&str> = cmd.get_arguments().map(|arg| arg.get_id().as_str()).collect();
+    subcommand_tools(&cmd, &[], &cli_args)
+}
+
+fn subcommand_tools(cmd: &Command, parents: &[String], cli_args: &[&str]) -> Vec<Tool> {
+    let mut tools = Vec::new();
     for subcommand in cmd.get_subcommands().filter(|s| s.get_name() != "help") {
+        let command = [parents, &[subcommand.get_name().to_string()]].concat();
         if subcommand.has_subcommands() {
-            subcommands.extend(get_chatgpt_subcommands(subcommand, &children_prefix).into_iter());
+            tools.extend(subcommand_tools(subcommand, &command, cli_args));
         } else {
-            subcommands.push(ChatGPTCommand::from_cmd(subcommand, children_prefix.clone()));
+            tools.push(Tool::from_cmd(subcommand, command, cli_args));
         }
     }
-    subcommands
+    tools
 }
-#[derive(Serialize)]
-pub struct ChatGPTCommand {
-    #[serde(rename = "type")]
-    cmd_type: String,
-    function: ChatGPTFunction,
+
+/// A command as a tool an assistant can call
+pub struct Tool {
+    /// The subcommands joined with `_`, like `music_set-shuffle`
+    pub name: String,
+    /// The subcommands that run it, like `["music", "set-shuffle"]`
+    pub command: Vec<String>,
+    pub description: String,
+    pub parameters: ToolParameters,
 }
 
-impl ChatGPTCommand {
-    pub fn from_cmd(cmd: &Command, path: String) -> Self {
-        let arguments = cmd.get_arguments().filter(|i| !i.is_hide_set());
+impl Tool {
+    pub fn from_cmd(cmd: &Command, command: Vec<String>, cli_args: &[&str]) -> Self {
+        let arguments: Vec<&Arg> = cmd
+            .get_arguments()
+            .filter(|a| !a.is_hide_set() && a.get_id() != "help" && !cli_args.contains(&a.get_id().as_str()))
+            .collect();
 
-        let mut properties: HashMap<String, ChatGPTFunctionProperty> = HashMap::new();
+        let mut properties: HashMap<String, ToolProperty> = HashMap::new();
         let mut req_properites: Vec<String> = Vec::new();
 
-        for arg in arguments.filter(|a| a.get_id() != "help") {
-            let name = arg.get_id().to_string();
+        for &arg in &arguments {
+            let name = property_name(arg.get_id().as_str());
             let description = arg.get_help().map(|txt| txt.to_string());
-            let enum_values: Option<Vec<String>> = match arg.get_possible_values() {
-                vec if vec.is_empty() => None,
-                vec => Some(vec.into_iter().map(|v| v.get_name().into()).collect()),
-            };
-
             if arg.is_required_set() {
                 req_properites.push(name.clone());
             }
 
             properties.insert(
-                name.replace("_", "-"),
-                ChatGPTFunctionProperty {
+                name,
+                ToolProperty {
                     description,
-                    enum_values,
                     index: arg.get_index(),
+                    schema: ValueSchema::from_arg(arg),
+                    default: default_value(arg),
+                    flag: arg
+                        .get_long()
+                        .map(|long| format!("--{}", long))
+                        .or(arg.get_short().map(|short| format!("-{}", short))),
+                    action: arg.get_action().clone(),
                 },
             );
         }
 
         Self {
-            cmd_type: "function".into(),
-            function: ChatGPTFunction {
-                name: format!("{}{}", path, cmd.get_name()),
-                description: cmd.get_about().map(|txt| txt.to_string()).unwrap_or("No Description".into()),
-                parameters: ChatGPTFunctionParams {
-                    param_type: "object".into(),
-                    required: req_properites,
-                    properties,
-                },
+            name: command.join("_"),
+            command,
+            description: cmd.get_about().map(|txt| txt.to_string()).unwrap_or("No Description".into()),
+            parameters: ToolParameters {
+                param_type: "object".into(),
+                required: req_properites,
+                properties,
+                constraints: constraints(cmd, &arguments),
             },
         }
     }
 }
 
-#[derive(Serialize)]
-pub struct ChatGPTFunction {
-    name: String,
-    description: String,
-    parameters: ChatGPTFunctionParams,
+#[derive(Error, Debug)]
+pub enum ToolCallError {
+    #[error("Unknown argument `{0}`{}", did_you_mean(.1))]
+    UnknownArgument(String, Option<String>),
+    #[error("Missing the required argument `{0}`")]
+    MissingArgument(String),
+    #[error("Invalid value {1} for `{0}`: {2}")]
+    InvalidValue(String, Value, String),
+    #[error("The arguments don't follow the command's rule: {0}")]
+    BrokenRule(String),
+}
+
+impl ToolCallError {
+    /// The argument that was probably meant instead of an unknown one
+    pub fn suggestion(&self) -> Option<&str> {
+        match self {
+            ToolCallError::UnknownArgument(_, suggestion) => suggestion.as_deref(),
+            _ => None,
+        }
+    }
 }
 
+impl Tool {
+    /// Checks the arguments of a call against the tool's schema, so a call clap would reject gets a reason the model
+    /// can act on. Null arguments count as not given
+    pub fn validate(&self, arguments: &Map<String, Value>) -> Result<(), ToolCallError> {
+        let properties = &self.parameters.properties;
+        let given: BTreeSet<&str> = arguments
+            .iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(name, _)| name.as_str())
+            .collect();
+        for &name in &given {
+            let suggestion = || closest(name, properties.keys().map(String::as_str)).map(String::from);
+            let property = properties
+                .get(name)
+                .ok_or_else(|| ToolCallError::UnknownArgument(name.into(), suggestion()))?;
+            let value = &arguments[name];
+            property
+                .schema
+                .check(value)
+                .map_err(|reason| ToolCallError::InvalidValue(name.into(), value.clone(), reason))?;
+        }
+        if let Some(missing) = self.parameters.required.iter().find(|name| !given.contains(name.as_str())) {
+            return Err(ToolCallError::MissingArgument(missing.clone()));
+        }
+
+        let constraints = &self.parameters.constraints;
+        let rules = match constraints.get("allOf").and_then(Value::as_array) {
+            Some(rules) => rules.clone(),
+            None => vec![Value::Object(constraints.clone())],
+        };
+        match rules.iter().find(|rule| !follows_rule(rule, &given)) {
+            Some(rule) => Err(ToolCallError::BrokenRule(describe_rule(rule))),
+            None => Ok(()),
+        }
+    }
+
+    /// The command line that runs a call of the tool, without the binary's name: the subcommands, the options as
+    /// `--<name> <value>` (flags only when they're set) and then the positional args in order, after a `--` so the
+    /// ones starting with `-` aren't taken for options. The arguments are validated first
+    pub fn argv(&self, arguments: &Map<String, Value>) -> Result<Vec<String>, ToolCallError> {
+        self.validate(arguments)?;
+        let mut options = Vec::new();
+        let mut positionals = Vec::new();
+        for (name, value) in arguments.iter().filter(|(_, value)| !value.is_null()) {
+            let property = &self.parameters.properties[name];
+            let invalid = || ToolCallError::InvalidValue(name.clone(), value.clone(), "it can't be given on the command line".into());
+            match (&property.flag, &property.action) {
+                (Some(flag), ArgAction::SetTrue | ArgAction::SetFalse) => {
+                    let set = value.as_bool().ok_or_else(invalid)?;
+                    if set == matches!(property.action, ArgAction::SetTrue) {
+                        options.push(flag.clone());
+                    }
+                }
+                (Some(flag), ArgAction::Count) => {
+                    let count = value.as_u64().ok_or_else(invalid)?;
+                    options.extend((0..count).map(|_| flag.clone()));
+                }
+                (Some(flag), _) => {
+                    for value in command_line_values(value).ok_or_else(invalid)? {
+                        options.extend([flag.clone(), value]);
+                    }
+                }
+                (None, _) => positionals.push((property.index, command_line_values(value).ok_or_else(invalid)?)),
+            }
+        }
+        positionals.sort_by_key(|(index, _)| *index);
+        let positionals: Vec<String> = positionals.into_iter().flat_map(|(_, values)| values).collect();
+        let separator = if positionals.is_empty() { vec![] } else { vec!["--".to_string()] };
+        Ok([self.command.clone(), options, separator, positionals].concat())
+    }
+}
+
+/// Whether the given args follow a rule made by `constraints`
+fn follows_rule(rule: &Value, given: &BTreeSet<&str>) -> bool {
+    let all = |key: &str| rule.get(key).and_then(Value::as_array).into_iter().flatten();
+    let required = all("required").all(|name| name.as_str().is_some_and(|name| given.contains(name)));
+    let any_of = rule.get("anyOf").is_none() || all("anyOf").any(|rule| follows_rule(rule, given));
+    let one_of = rule.get("oneOf").is_none() || all("oneOf").filter(|rule| follows_rule(rule, given)).count() == 1;
+    let all_of = all("allOf").all(|rule| follows_rule(rule, given));
+    let not = rule.get("not").is_none_or(|rule| !follows_rule(rule, given));
+    required && any_of && one_of && all_of && not
+}
+
+/// The rule in words, like "either `track` is given or `context` is given"
+fn describe_rule(rule: &Value) -> String {
+    let all = |key: &str| {
+        rule.get(key)
+            .and_then(Value::as_array)
+            .map(|rules| rules.iter().map(describe_rule).collect::<Vec<_>>())
+    };
+    let mut parts = Vec::new();
+    if let Some(names) = rule.get("required").and_then(Value::as_array) {
+        let names: Vec<String> = names.iter().filter_map(Value::as_str).map(|name| format!("`{}`", name)).collect();
+        parts.push(format!("{} {} given", names.join(" and "), if names.len() == 1 { "is" } else { "are" }));
+    }
+    if let Some(rules) = all("anyOf") {
+        parts.push(format!("either {}", rules.join(" or ")));
+    }
+    if let Some(rules) = all("oneOf") {
+        parts.push(format!("exactly one of: {}", rules.join(", ")));
+    }
+    if let Some(rules) = all("allOf") {
+        parts.extend(rules);
+    }
+    if let Some(rule) = rule.get("not") {
+        parts.push(format!("not ({})", describe_rule(rule)));
+    }
+    parts.join(" and ")
+}
+
+/// The candidate closest to the name, if it's close enough for the name to be a typo of it
+pub(crate) fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= (name.len().max(candidate.len()) / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// The number of characters to insert, remove or replace to turn one string into the other
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let replace = previous[j] + usize::from(a_char != *b_char);
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// `, did you mean `<suggestion>`?` if there's a suggestion
+pub(crate) fn did_you_mean(suggestion: &Option<String>) -> String {
+    suggestion
+        .as_ref()
+        .map(|suggestion| format!(", did you mean `{}`?", suggestion))
+        .unwrap_or_default()
+}
+
+/// The values as they're given on the command line, `None` for values that can't be given there
+fn command_line_values(value: &Value) -> Option<Vec<String>> {
+    match value {
+        Value::Array(values) => values.iter().map(command_line_value).collect(),
+        value => command_line_value(value).map(|value| vec![value]),
+    }
+}
+
+fn command_line_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(_) | Value::Bool(_) => Some(value.to_string()),
+        Value::Null | Value::Array(_) | Value::Object(_) => None,
+    }
+}
+
+impl SpecFormat {
+    pub fn render(&self, tools: &[Tool]) -> Value {
+        let schemas = tools.iter().map(|tool| (tool, serde_json::to_value(&tool.parameters).unwrap()));
+        match self {
+            SpecFormat::Openai => schemas
+                .map(|(tool, parameters)| {
+                    json!({
+                        "type": "function",
+                        "function": { "name": tool.name, "description": tool.description, "parameters": parameters },
+                    })
+                })
+                .collect(),
+            SpecFormat::Anthropic => schemas
+                .map(|(tool, parameters)| json!({ "name": tool.name, "description": tool.description, "input_schema": parameters }))
+                .collect(),
+            SpecFormat::Gemini => {
+                let declarations: Vec<Value> = schemas
+                    .map(|(tool, parameters)| {
+                        let mut declaration = json!({ "name": tool.name, "description": tool.description });
+                        // Gemini rejects objects without properties, a tool without arguments has no parameters instead
+                        if !tool.parameters.properties.is_empty() {
+                            declaration["parameters"] = gemini_schema(parameters);
+                        }
+                        declaration
+                    })
+                    .collect();
+                json!([{ "functionDeclarations": declarations }])
+            }
+            SpecFormat::Openapi => {
+                let paths: Map<String, Value> = schemas
+                    .map(|(tool, parameters)| {
+                        let mut operation = json!({
+                            "operationId": tool.name,
+                            "description": tool.description,
+                            "responses": { "200": { "description": "The output of the command" } },
+                        });
+                        if !tool.parameters.properties.is_empty() {
+                            operation["requestBody"] = json!({ "content": { "application/json": { "schema": parameters } } });
+                        }
+                        (format!("/{}", tool.command.join("/")), json!({ "post": operation }))
+                    })
+                    .collect();
+                json!({
+                    "openapi": "3.1.0",
+                    "info": { "title": "aerial-utils", "version": env!("CARGO_PKG_VERSION") },
+                    "paths": paths,
+                })
+            }
+            SpecFormat::Jsonschema => {
+                let schemas = schemas.map(|(tool, parameters)| {
+                    let mut schema = json!({
+                        "$schema": "https://json-schema.org/draft/2020-12/schema",
+                        "title": tool.name,
+                        "description": tool.description,
+                    });
+                    if let (Some(schema), Value::Object(parameters)) = (schema.as_object_mut(), parameters) {
+                        schema.extend(parameters);
+                    }
+                    (tool.name.clone(), schema)
+                });
+                Value::Object(schemas.collect())
+            }
+        }
+    }
+}
+
+/// Gemini's schemas are a subset of JSON Schema that rejects anything else, so the positions of the args and the schemas
+/// combining others are left out
+fn gemini_schema(mut parameters: Value) -> Value {
+    if let Some(parameters) = parameters.as_object_mut() {
+        parameters.retain(|key, _| !["anyOf", "oneOf", "allOf", "not"].contains(&key.as_str()));
+        for property in parameters
+            .get_mut("properties")
+            .and_then(Value::as_object_mut)
+            .into_iter()
+            .flat_map(|p| p.values_mut())
+        {
+            if let Some(property) = property.as_object_mut() {
+                property.remove("index");
+            }
+        }
+    }
+    parameters
+}
+
+/// The JSON Schema of a tool's arguments
 #[derive(Serialize)]
-pub struct ChatGPTFunctionParams {
+pub struct ToolParameters {
     #[serde(rename = "type")]
     param_type: String,
-    properties: HashMap<String, ChatGPTFunctionProperty>,
+    properties: HashMap<String, ToolProperty>,
     required: Vec<String>,
+    /// What clap checks on top of the required args, as `anyOf`/`oneOf`/`not` (or `allOf` of them if there are several)
+    #[serde(flatten)]
+    constraints: Map<String, Value>,
+}
+
+fn property_name(id: &str) -> String {
+    id.replace("_", "-")
+}
+
+/// The rules clap checks about which args can be given together, for the args in the spec:
+/// - a required group needs one of its args (`anyOf`), or exactly one if it doesn't allow several (`oneOf`)
+/// - a group that doesn't allow several args can't have two of them (`not`)
+/// - conflicting args can't both be given (`not`)
+/// - an arg that requires others can only be given with them (`anyOf` of leaving it out and giving them)
+fn constraints(cmd: &Command, arguments: &[&Arg]) -> Map<String, Value> {
+    let included: BTreeSet<&str> = arguments.iter().map(|arg| arg.get_id().as_str()).collect();
+    let required = |ids: &[&str]| json!({ "required": ids.iter().map(|id| property_name(id)).collect::<Vec<_>>() });
+    let mut constraints = Vec::new();
+
+    for group in cmd.get_groups() {
+        let args: Vec<&str> = group.get_args().map(|id| id.as_str()).filter(|id| included.contains(id)).collect();
+        let each = args.iter().map(|id| required(&[id])).collect::<Vec<_>>();
+        let pairs = args.iter().enumerate().flat_map(|(i, a)| args[i + 1..].iter().map(|b| required(&[a, b])));
+        match (group.is_required_set(), group.clone().is_multiple()) {
+            _ if args.is_empty() => {}
+            (true, true) => constraints.push(json!({ "anyOf": each })),
+            (true, false) => constraints.push(json!({ "oneOf": each })),
+            (false, false) if args.len() > 1 => constraints.push(json!({ "not": { "anyOf": pairs.collect::<Vec<_>>() } })),
+            (false, _) => {}
+        }
+    }
+
+    let mut conflicts = BTreeSet::new();
+    for arg in arguments {
+        for other in cmd
+            .get_arg_conflicts_with(arg)
+            .into_iter()
+            .filter(|other| included.contains(other.get_id().as_str()))
+        {
+            let (a, b) = (arg.get_id().as_str(), other.get_id().as_str());
+            conflicts.insert((a.min(b), a.max(b)));
+        }
+    }
+    constraints.extend(conflicts.into_iter().map(|(a, b)| json!({ "not": required(&[a, b]) })));
+
+    for (id, requires) in required_args(cmd) {
+        let requires: Vec<&str> = requires.iter().map(String::as_str).filter(|other| included.contains(other)).collect();
+        if included.contains(id.as_str()) && !requires.is_empty() {
+            constraints.push(json!({ "anyOf": [{ "not": required(&[&id]) }, required(&requires)] }));
+        }
+    }
+
+    let mut map = Map::new();
+    match constraints.len() {
+        0 => {}
+        1 => map = constraints.remove(0).as_object().cloned().unwrap_or_default(),
+        _ => {
+            map.insert("allOf".into(), constraints.into());
+        }
+    }
+    map
+}
+
+/// The args each arg requires, directly or through its group. clap doesn't expose them, so each arg is given on its own
+/// to a copy of the command where nothing is required otherwise, and the args it reports as missing are the required ones.
+/// In the copy every arg is a `--<id>` option taking any value, so the missing args can be told apart by their name
+fn required_args(cmd: &Command) -> Vec<(String, Vec<String>)> {
+    // A new command, since the copy of a built one would keep parsing its args as they were. It adds its own help flag
+    let args = cmd.get_arguments().filter(|arg| !is_help(arg)).map(|arg| {
+        let arg = arg.clone().required(false).index(None).short(None).long(arg.get_id().to_string());
+        match arg.get_action().takes_values() {
+            true => arg.value_parser(clap::value_parser!(String)),
+            false => arg,
+        }
+    });
+    let groups = cmd.get_groups().map(|group| group.clone().required(false));
+    let mut probe = Command::new(cmd.get_name().to_string()).no_binary_name(true).args(args).groups(groups);
+    probe.build();
+
+    let missing = |argv: Vec<String>| -> BTreeSet<String> {
+        let Err(err) = probe.clone().try_get_matches_from(argv) else {
+            return BTreeSet::new();
+        };
+        match (err.kind(), err.get(ContextKind::InvalidArg)) {
+            (ErrorKind::MissingRequiredArgument, Some(ContextValue::Strings(args))) => args
+                .iter()
+                .filter_map(|arg| arg.strip_prefix("--")?.split([' ', '=']).next().map(String::from))
+                .collect(),
+            _ => BTreeSet::new(),
+        }
+    };
+    let always_missing = missing(Vec::new());
+    probe
+        .get_arguments()
+        .filter(|arg| !is_help(arg))
+        .map(|arg| {
+            let id = arg.get_id().to_string();
+            let argv = match arg.get_action().takes_values() {
+                true => vec![format!("--{}=value", id)],
+                false => vec![format!("--{}", id)],
+            };
+            (id, missing(argv).difference(&always_missing).cloned().collect())
+        })
+        .collect()
 }
 
 #[derive(Serialize)]
-pub struct ChatGPTFunctionProperty {
+pub struct ToolProperty {
     #[serde(skip_serializing_if = "Option::is_none")]
     index: Option<usize>,
     #[serde(skip_serializing_if = "Option::is_none")]
     description: Option<String>,
+    #[serde(flatten)]
+    schema: ValueSchema,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<Value>,
+    /// `--long` or `-s`, `None` for positional args
+    #[serde(skip)]
+    flag: Option<String>,
+    #[serde(skip)]
+    action: ArgAction,
+}
 
+/// The JSON Schema of an argument's value, or of the items when it takes several values
+#[derive(Serialize)]
+pub struct ValueSchema {
+    #[serde(rename = "type")]
+    value_type: &'static str,
     #[serde(rename = "enum")]
     #[serde(skip_serializing_if = "Option::is_none")]
     enum_values: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    minimum: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    maximum: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    items: Option<Box<ValueSchema>>,
+}
+
+impl ValueSchema {
+    /// Checks the value's type, allowed values and range, the reason it's invalid otherwise
+    fn check(&self, value: &Value) -> Result<(), String> {
+        let is_type = match self.value_type {
+            "array" => {
+                let Value::Array(values) = value else {
+                    return Err("expected an array".into());
+                };
+                return values
+                    .iter()
+                    .try_for_each(|value| self.items.as_ref().map_or(Ok(()), |items| items.check(value)));
+            }
+            "integer" => value.is_i64() || value.is_u64(),
+            "number" => value.is_number(),
+            "boolean" => value.is_boolean(),
+            _ => value.is_string(),
+        };
+        if !is_type {
+            let article = if self.value_type == "integer" { "an" } else { "a" };
+            return Err(format!("expected {} {}", article, self.value_type));
+        }
+        if let (Some(values), Some(value)) = (&self.enum_values, value.as_str()) {
+            if !values.iter().any(|allowed| allowed == value) {
+                let allowed: Vec<String> = values.iter().map(|allowed| format!("`{}`", allowed)).collect();
+                let suggestion = closest(value, values.iter().map(String::as_str)).map(String::from);
+                return Err(format!("expected one of {}{}", allowed.join(", "), did_you_mean(&suggestion)));
+            }
+        }
+        match value.as_i64() {
+            Some(value) if self.minimum.is_some_and(|minimum| value < minimum) => Err(format!("the minimum is {}", self.minimum.unwrap())),
+            Some(value) if self.maximum.is_some_and(|maximum| value > maximum) => Err(format!("the maximum is {}", self.maximum.unwrap())),
+            _ => Ok(()),
+        }
+    }
+
+    fn from_arg(arg: &Arg) -> Self {
+        let value_type = value_type(arg);
+        let enum_values: Option<Vec<String>> = match arg.get_possible_values() {
+            vec if vec.is_empty() || value_type == "boolean" => None,
+            vec => Some(vec.into_iter().map(|v| v.get_name().into()).collect()),
+        };
+        let (minimum, maximum) = match integer_bounds(arg) {
+            Some(bounds) if arg.get_action().takes_values() => integer_range(arg, bounds),
+            _ => (None, None),
+        };
+        let schema = Self {
+            value_type,
+            enum_values,
+            minimum,
+            maximum,
+            items: None,
+        };
+
+        match is_array(arg) {
+            true => Self {
+                value_type: "array",
+                enum_values: None,
+                minimum: None,
+                maximum: None,
+                items: Some(Box::new(schema)),
+            },
+            false => schema,
+        }
+    }
+}
+
+fn is_array(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::Append) || arg.get_num_args().is_some_and(|num_args| num_args.max_values() > 1)
+}
+
+/// The type of a single value, flags are booleans and counted flags integers whatever their value parser is
+fn value_type(arg: &Arg) -> &'static str {
+    let type_id = arg.get_value_parser().type_id();
+    match arg.get_action() {
+        ArgAction::SetTrue | ArgAction::SetFalse => "boolean",
+        ArgAction::Count => "integer",
+        _ if integer_bounds(arg).is_some() => "integer",
+        _ if type_id == TypeId::of::<f32>() || type_id == TypeId::of::<f64>() => "number",
+        _ if type_id == TypeId::of::<bool>() => "boolean",
+        _ => "string",
+    }
+}
+
+fn is_help(arg: &Arg) -> bool {
+    matches!(
+        arg.get_action(),
+        ArgAction::Help | ArgAction::HelpShort | ArgAction::HelpLong | ArgAction::Version
+    )
+}
+
+/// The bounds of the integer type the arg is parsed into, as far as they fit in an `i64`
+fn integer_bounds(arg: &Arg) -> Option<(i64, i64)> {
+    let type_id = arg.get_value_parser().type_id();
+    [
+        (TypeId::of::<i8>(), i8::MIN as i64, i8::MAX as i64),
+        (TypeId::of::<i16>(), i16::MIN as i64, i16::MAX as i64),
+        (TypeId::of::<i32>(), i32::MIN as i64, i32::MAX as i64),
+        (TypeId::of::<i64>(), i64::MIN, i64::MAX),
+        (TypeId::of::<isize>(), isize::MIN as i64, isize::MAX as i64),
+        (TypeId::of::<u8>(), 0, u8::MAX as i64),
+        (TypeId::of::<u16>(), 0, u16::MAX as i64),
+        (TypeId::of::<u32>(), 0, u32::MAX as i64),
+        (TypeId::of::<u64>(), 0, i64::MAX),
+        (TypeId::of::<usize>(), 0, i64::MAX),
+    ]
+    .into_iter()
+    .find(|(id, ..)| type_id == *id)
+    .map(|(_, min, max)| (min, max))
+}
+
+/// The minimum and maximum the arg's value parser accepts, when they're narrower than the type's own bounds (the minimum
+/// of unsigned types is included as well). clap doesn't expose the range of a value parser, so it's found by trying values
+fn integer_range(arg: &Arg, (min, max): (i64, i64)) -> (Option<i64>, Option<i64>) {
+    let probe = Command::new("probe")
+        .no_binary_name(true)
+        .arg(Arg::new("value").long("value").value_parser(arg.get_value_parser().clone()));
+    let accepts = |value: i64| probe.clone().try_get_matches_from([format!("--value={}", value)]).is_ok();
+    let Some(start) = [0, 1, -1, min, max]
+        .into_iter()
+        .find(|value| (min..=max).contains(value) && accepts(*value))
+    else {
+        return (None, None);
+    };
+
+    // Ranges are contiguous, so the bounds are where values start being rejected on either side of an accepted value
+    let half = |low: i64, high: i64| ((high as i128 - low as i128) / 2) as i64;
+    let (mut low, mut high) = (min, start);
+    while low < high {
+        let mid = low + half(low, high);
+        match accepts(mid) {
+            true => high = mid,
+            false => low = mid + 1,
+        }
+    }
+    let minimum = low;
+    let (mut low, mut high) = (start, max);
+    while low < high {
+        let mid = high - half(low, high);
+        match accepts(mid) {
+            true => low = mid,
+            false => high = mid - 1,
+        }
+    }
+    let maximum = high;
+
+    ((minimum != min || min == 0).then_some(minimum), (maximum != max).then_some(maximum))
+}
+
+/// The default value as its JSON type, an array of the defaults for args taking several values
+fn default_value(arg: &Arg) -> Option<Value> {
+    let value_type = value_type(arg);
+    let typed = |value: &std::ffi::OsStr| {
+        let value = value.to_string_lossy();
+        let parsed = match value_type {
+            "integer" => value.parse::<i64>().ok().map(Value::from),
+            "number" => value.parse::<f64>().ok().map(Value::from),
+            "boolean" => value.parse::<bool>().ok().map(Value::from),
+            _ => None,
+        };
+        parsed.unwrap_or_else(|| Value::from(value.to_string()))
+    };
+    let mut defaults = arg.get_default_values().iter().map(|value| typed(value));
+    match is_array(arg) {
+        true if !arg.get_default_values().is_empty() => Some(Value::Array(defaults.collect())),
+        true => None,
+        false => defaults.next(),
+    }
 }
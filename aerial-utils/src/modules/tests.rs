@@ -0,0 +1,506 @@
+use super::{
+    plugin::{Plugin, PluginError},
+    spec_gen::{tools, SpecFormat, Tool, ToolCallError},
+    ModuleRegistry,
+};
+use crate::{
+    cli::{run_module, tools_command, AerialUtilsArgs, AppError, Paths},
+    mcp::{McpServer, PROTOCOL_VERSIONS},
+    tool_call::{exec_tool, exec_tool_in, ToolRunner},
+    utils::{oauth::random_string, output::OutputFormat, Cache, Config},
+};
+use clap::ArgMatches;
+use serde_json::{json, Map, Value};
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("aerial-test-{}", random_string(12)));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The CLI's args with the files in `dir`, and the plugins in `dir/bin`
+fn args_in(dir: &Path, args: &[&str]) -> (ModuleRegistry, AerialUtilsArgs) {
+    let registry = ModuleRegistry::default().with_plugins(Some(dir.join("bin").into()));
+    let (config, cache) = (dir.join("config.toml"), dir.join("cache.toml"));
+    let files = ["--config", config.to_str().unwrap(), "--cache", cache.to_str().unwrap()];
+    let argv = ["aerial-utils"].iter().chain(&files).chain(args);
+    let args = AerialUtilsArgs::try_parse_with(&registry, argv).unwrap();
+    (registry, args)
+}
+
+fn run_in(dir: &Path, args: &[&str]) -> Result<(), AppError> {
+    let (registry, args) = args_in(dir, args);
+    let paths = Paths::resolve(&args);
+    run_module(args, &registry, &paths)
+}
+
+/// Sends the messages to an MCP server with the files and plugins in `dir` (see `args_in`), returning its responses
+fn mcp_in(dir: &Path, messages: &[Value]) -> Vec<Option<Value>> {
+    let (registry, args) = args_in(dir, &["mcp"]);
+    let paths = Paths::resolve(&args);
+    let config = Config::from_file(&paths.config.path).unwrap();
+    let server = McpServer::new(&registry, &paths, config);
+    messages.iter().map(|message| server.handle(&message.to_string())).collect()
+}
+
+fn request(id: u64, method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+}
+
+fn call_tool(name: &str, arguments: Value) -> Value {
+    request(1, "tools/call", json!({ "name": name, "arguments": arguments }))
+}
+
+#[test]
+fn disabled_module_is_not_listed() {
+    let registry = ModuleRegistry::default();
+    let enabled = |raw_config: &str| {
+        let config: Config = toml::from_str(raw_config).unwrap();
+        registry.enabled(&config).map(|module| module.name.clone()).collect::<Vec<_>>()
+    };
+    assert_eq!(enabled(""), ["music"]);
+    assert_eq!(enabled("[modules.spotify]\nclient_id = \"id\"\nenabled = true"), ["music"]);
+    assert!(enabled("[modules.spotify]\nclient_id = \"id\"\nenabled = false").is_empty());
+}
+
+#[test]
+fn disabled_module_is_not_run() {
+    let dir = temp_dir();
+    fs::write(dir.join("config.toml"), "[modules.spotify]\nclient_id = \"id\"\nenabled = false").unwrap();
+    let result = run_in(&dir, &["music", "next"]);
+    assert!(matches!(result, Err(AppError::ModuleDisabled(name)) if name == "music"));
+}
+
+/// The properties in the spec of a music subcommand
+fn music_properties(subcommand: &str) -> serde_json::Value {
+    let mut cmd = AerialUtilsArgs::command_with(&ModuleRegistry::default());
+    cmd.build();
+    let subcommand = cmd.find_subcommand("music").and_then(|music| music.find_subcommand(subcommand)).unwrap();
+    let tool = Tool::from_cmd(subcommand, vec!["music".into(), subcommand.get_name().into()], &[]);
+    serde_json::to_value(&tool.parameters).unwrap()["properties"].clone()
+}
+
+#[test]
+fn spec_types_come_from_the_args() {
+    let properties = music_properties("search");
+    assert_eq!(properties["query"]["type"], "string");
+    assert_eq!(properties["query"]["index"], 1);
+    assert_eq!(properties["all"]["type"], "boolean");
+    assert_eq!(properties["all"]["default"], false);
+    assert_eq!(properties["offset"]["type"], "integer");
+    assert_eq!(properties["search-type"]["type"], "string");
+    assert_eq!(
+        properties["search-type"]["enum"],
+        serde_json::json!(["track", "album", "artist", "playlist"])
+    );
+    assert_eq!(properties["search-type"]["default"], "track");
+}
+
+#[test]
+fn spec_includes_the_allowed_range() {
+    let properties = music_properties("search");
+    assert_eq!(properties["limit"]["minimum"], 1);
+    assert_eq!(properties["limit"]["maximum"], 50);
+    // Only the bounds the parser adds to the type's own are included
+    assert_eq!(properties["offset"]["minimum"], 0);
+    assert!(properties["offset"].get("maximum").is_none());
+}
+
+#[test]
+fn spec_of_args_with_several_values_is_an_array() {
+    let properties = music_properties("save");
+    assert_eq!(properties["ids"]["type"], "array");
+    assert_eq!(properties["ids"]["items"], serde_json::json!({ "type": "string" }));
+}
+
+#[test]
+fn spec_of_a_required_group_needs_one_of_its_args() {
+    let mut cmd = AerialUtilsArgs::command_with(&ModuleRegistry::default());
+    cmd.build();
+    let play = cmd.find_subcommand("music").and_then(|music| music.find_subcommand("play")).unwrap();
+    let tool = Tool::from_cmd(play, vec!["music".into(), "play".into()], &[]);
+    let expected = serde_json::json!([{ "required": ["track"] }, { "required": ["context"] }]);
+    assert_eq!(serde_json::to_value(&tool.parameters).unwrap()["anyOf"], expected);
+}
+
+#[test]
+fn spec_includes_conflicts_and_requirements() {
+    use clap::{Arg, ArgAction, ArgGroup, Command};
+    let mut cmd = Command::new("test")
+        .arg(Arg::new("query").index(1).requires("limit"))
+        .arg(Arg::new("limit").long("limit").value_parser(clap::value_parser!(u32)))
+        .arg(Arg::new("all").short('a').long("all").action(ArgAction::SetTrue).conflicts_with("limit"))
+        .arg(Arg::new("track").long("track"))
+        .arg(Arg::new("album").long("album"))
+        .group(ArgGroup::new("item").args(["track", "album"]).multiple(false));
+    cmd.build();
+    let tool = Tool::from_cmd(&cmd, vec!["test".into()], &[]);
+    let expected = serde_json::json!([
+        { "not": { "anyOf": [{ "required": ["track", "album"] }] } },
+        { "not": { "required": ["all", "limit"] } },
+        { "anyOf": [{ "not": { "required": ["query"] } }, { "required": ["limit"] }] },
+    ]);
+    assert_eq!(serde_json::to_value(&tool.parameters).unwrap()["allOf"], expected);
+}
+
+/// The tools of the CLI with the music module, in the format
+fn music_specs(format: SpecFormat) -> serde_json::Value {
+    format.render(&tools(tools_command(&ModuleRegistry::default(), &Config::default())))
+}
+
+#[test]
+fn specs_in_the_formats_of_the_providers() {
+    let openai = music_specs(SpecFormat::Openai);
+    let play = openai
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|tool| tool["function"]["name"] == "music_play")
+        .unwrap();
+    assert_eq!(play["type"], "function");
+    let parameters = &play["function"]["parameters"];
+    assert_eq!(parameters["properties"]["track"]["type"], "string");
+
+    let anthropic = music_specs(SpecFormat::Anthropic);
+    let play = anthropic.as_array().unwrap().iter().find(|tool| tool["name"] == "music_play").unwrap();
+    assert_eq!(&play["input_schema"], parameters);
+
+    let openapi = music_specs(SpecFormat::Openapi);
+    let play = &openapi["paths"]["/music/play"]["post"];
+    assert_eq!(play["operationId"], "music_play");
+    assert_eq!(&play["requestBody"]["content"]["application/json"]["schema"], parameters);
+
+    let schemas = music_specs(SpecFormat::Jsonschema);
+    assert_eq!(schemas["music_play"]["title"], "music_play");
+    assert_eq!(schemas["music_play"]["anyOf"], parameters["anyOf"]);
+}
+
+#[test]
+fn gemini_specs_leave_out_what_it_does_not_support() {
+    let gemini = music_specs(SpecFormat::Gemini);
+    let declarations = gemini[0]["functionDeclarations"].as_array().unwrap();
+    let find = |name: &str| declarations.iter().find(|declaration| declaration["name"] == name).unwrap();
+    assert!(find("music_play")["parameters"].get("anyOf").is_none());
+    assert!(find("music_search")["parameters"]["properties"]["query"].get("index").is_none());
+    // The CLI's own commands aren't tools
+    assert!(declarations
+        .iter()
+        .all(|declaration| declaration["name"] != "paths" && declaration["name"] != "exec-tool"));
+}
+
+fn music_tool(subcommand: &str) -> Tool {
+    let mut cmd = AerialUtilsArgs::command_with(&ModuleRegistry::default());
+    cmd.build();
+    let command = cmd.find_subcommand("music").and_then(|music| music.find_subcommand(subcommand)).unwrap();
+    Tool::from_cmd(command, vec!["music".into(), subcommand.into()], &[])
+}
+
+#[test]
+fn tool_calls_become_command_lines() {
+    let arguments = json!({ "query": "some song", "limit": 5, "all": true, "search-type": "album", "profile": null });
+    let argv = music_tool("search").argv(arguments.as_object().unwrap()).unwrap();
+    assert_eq!(
+        argv,
+        ["music", "search", "--all", "--limit", "5", "--search-type", "album", "--", "some song"]
+    );
+
+    let argv = music_tool("search")
+        .argv(json!({ "query": "q", "all": false }).as_object().unwrap())
+        .unwrap();
+    assert_eq!(argv, ["music", "search", "--", "q"]);
+    let argv = music_tool("save").argv(json!({ "ids": ["a", "b"] }).as_object().unwrap()).unwrap();
+    assert_eq!(argv, ["music", "save", "--", "a", "b"]);
+    let argv = music_tool("next").argv(&Map::new()).unwrap();
+    assert_eq!(argv, ["music", "next"]);
+}
+
+#[test]
+fn tool_call_values_starting_with_a_dash_are_not_options() {
+    let argv = music_tool("search")
+        .argv(json!({ "query": "-x", "search-type": "album" }).as_object().unwrap())
+        .unwrap();
+    let args = AerialUtilsArgs::try_parse_with(&ModuleRegistry::default(), ["aerial-utils".to_string()].into_iter().chain(argv)).unwrap();
+    let (_, matches) = args.module.unwrap();
+    let search = matches.subcommand_matches("search").unwrap();
+    assert_eq!(search.get_one::<String>("query").map(String::as_str), Some("-x"));
+}
+
+#[test]
+fn invalid_tool_calls_are_errors() {
+    let result = music_tool("search").argv(json!({ "query": "q", "colour": "red" }).as_object().unwrap());
+    assert!(matches!(result, Err(ToolCallError::UnknownArgument(name, None)) if name == "colour"));
+    let result = music_tool("search").argv(json!({ "query": { "text": "q" } }).as_object().unwrap());
+    assert!(matches!(result, Err(ToolCallError::InvalidValue(name, ..)) if name == "query"));
+}
+
+#[test]
+fn tool_calls_are_validated_against_the_schema() {
+    let validate = |subcommand: &str, arguments: Value| music_tool(subcommand).validate(arguments.as_object().unwrap());
+    let result = validate("search", json!({ "query": "q", "limt": 3 }));
+    assert!(matches!(&result, Err(ToolCallError::UnknownArgument(name, _)) if name == "limt"));
+    assert_eq!(result.unwrap_err().suggestion(), Some("limit"));
+    let result = validate("search", json!({ "query": "q", "limit": 99 }));
+    assert_eq!(result.unwrap_err().to_string(), "Invalid value 99 for `limit`: the maximum is 50");
+    let result = validate("search", json!({ "query": "q", "search-type": "albm" }));
+    assert!(result.unwrap_err().to_string().ends_with("did you mean `album`?"));
+    let result = validate("search", json!({ "query": "q", "limit": "5" }));
+    assert!(matches!(result, Err(ToolCallError::InvalidValue(_, _, reason)) if reason == "expected an integer"));
+    assert!(matches!(validate("search", json!({})), Err(ToolCallError::MissingArgument(name)) if name == "query"));
+    assert!(matches!(validate("play", json!({})), Err(ToolCallError::BrokenRule(_))));
+    assert!(validate("play", json!({ "track": "t", "profile": null })).is_ok());
+}
+
+#[test]
+fn exec_tool_prints_the_result_or_error() {
+    let dir = temp_dir();
+    fs::write(dir.join("config.toml"), "[modules.spotify]\nclient_id = \"id\"").unwrap();
+    let (registry, args) = args_in(&dir, &["exec-tool"]);
+    let paths = Paths::resolve(&args);
+    let runner = ToolRunner::new(&registry, &paths, Config::from_file(&paths.config.path).unwrap());
+
+    let output = exec_tool(&runner, Some(r#"{"name": "music_profiles_list", "arguments": "{}"}"#));
+    assert_eq!(output["result"][0]["name"], "default");
+    let output = exec_tool(&runner, Some(r#"{"name": "music_profiles_lst"}"#));
+    assert_eq!(output["error"]["kind"], "unknown_tool");
+    assert_eq!(output["error"]["suggestion"], "music_profiles_list");
+    let output = exec_tool(&runner, Some(r#"{"name": "music_search", "arguments": {"query": "q", "limt": 3}}"#));
+    assert_eq!(
+        output["error"],
+        json!({ "kind": "invalid_arguments", "message": "Unknown argument `limt`, did you mean `limit`?", "suggestion": "limit" })
+    );
+    assert_eq!(exec_tool(&runner, Some("music_search"))["error"]["kind"], "invalid_tool_call");
+}
+
+#[test]
+fn exec_tool_prints_unreadable_files_as_errors() {
+    let dir = temp_dir();
+    fs::write(dir.join("config.toml"), "[modules.spotify]\nclient_id = \"id\"").unwrap();
+    fs::write(dir.join("cache.toml"), "not toml").unwrap();
+    let (registry, args) = args_in(&dir, &["exec-tool"]);
+    let paths = Paths::resolve(&args);
+    let output = exec_tool_in(&registry, &paths, Some(r#"{"name": "music_profiles_list"}"#));
+    assert_eq!(output["error"]["kind"], "cache_error");
+
+    fs::write(dir.join("config.toml"), "[modules").unwrap();
+    let output = exec_tool_in(&registry, &paths, Some(r#"{"name": "music_profiles_list"}"#));
+    assert_eq!(output["error"]["kind"], "config_error");
+}
+
+#[test]
+fn matches_that_do_not_fit_the_module_are_an_error() {
+    let dir = temp_dir();
+    fs::write(dir.join("config.toml"), "[modules.spotify]\nclient_id = \"id\"").unwrap();
+    let (registry, args) = args_in(&dir, &["paths"]);
+    let paths = Paths::resolve(&args);
+    let config = Config::from_file(&paths.config.path).unwrap();
+    let music = registry.get("music").unwrap();
+    let result = music.output(&ArgMatches::default(), &config, &mut Cache::default(), &paths, OutputFormat::Json);
+    assert!(matches!(result, Err(AppError::InvalidArguments(_))));
+}
+
+#[test]
+fn mcp_server_agrees_on_a_protocol_version() {
+    let initialize = |version: &str| request(1, "initialize", json!({ "protocolVersion": version, "capabilities": {} }));
+    let responses = mcp_in(&temp_dir(), &[initialize("2024-11-05"), initialize("1999-01-01")]);
+    let result = &responses[0].as_ref().unwrap()["result"];
+    assert_eq!(result["protocolVersion"], "2024-11-05");
+    assert_eq!(result["serverInfo"]["name"], "aerial-utils");
+    assert!(result["capabilities"]["tools"].is_object());
+    assert_eq!(responses[1].as_ref().unwrap()["result"]["protocolVersion"], PROTOCOL_VERSIONS[0]);
+}
+
+#[test]
+fn mcp_tools_are_the_module_commands() {
+    let responses = mcp_in(&temp_dir(), &[request(1, "tools/list", json!({}))]);
+    let tools = responses[0].as_ref().unwrap()["result"]["tools"].as_array().unwrap().clone();
+    let names: Vec<&str> = tools.iter().filter_map(|tool| tool["name"].as_str()).collect();
+    assert!(names.contains(&"music_play") && names.contains(&"music_profiles_list"));
+    assert!(!names.contains(&"paths") && !names.contains(&"command-specs") && !names.contains(&"mcp"));
+    let play = tools.iter().find(|tool| tool["name"] == "music_play").unwrap();
+    assert_eq!(play["inputSchema"]["type"], "object");
+    assert_eq!(
+        play["inputSchema"]["anyOf"],
+        json!([{ "required": ["track"] }, { "required": ["context"] }])
+    );
+}
+
+#[test]
+fn mcp_tool_calls_run_the_command() {
+    let dir = temp_dir();
+    fs::write(dir.join("config.toml"), "[modules.spotify]\nclient_id = \"id\"").unwrap();
+    let responses = mcp_in(&dir, &[call_tool("music_profiles_list", json!({}))]);
+    let result = &responses[0].as_ref().unwrap()["result"];
+    assert_eq!(result["isError"], false);
+    let profiles: Value = serde_json::from_str(result["content"][0]["text"].as_str().unwrap()).unwrap();
+    assert_eq!(profiles[0]["name"], "default");
+}
+
+#[test]
+fn failing_mcp_tool_calls_are_error_results() {
+    let responses = mcp_in(&temp_dir(), &[call_tool("music_profiles_list", json!({}))]);
+    let result = &responses[0].as_ref().unwrap()["result"];
+    assert_eq!(result["isError"], true);
+    let error: Value = serde_json::from_str(result["content"][0]["text"].as_str().unwrap()).unwrap();
+    assert_eq!(error["error"]["kind"], "missing_config");
+}
+
+#[test]
+fn mcp_protocol_errors() {
+    let notification = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+    let messages = [
+        request(1, "resources/list", json!({})),
+        call_tool("music_dance", json!({})),
+        call_tool("music_search", json!({ "colour": "red" })),
+        call_tool("music_search", json!({ "query": "q", "limit": 99 })),
+        notification,
+    ];
+    let responses = mcp_in(&temp_dir(), &messages);
+    let code = |response: &Option<Value>| response.as_ref().unwrap()["error"]["code"].as_i64().unwrap();
+    assert_eq!(code(&responses[0]), -32601);
+    assert_eq!(code(&responses[1]), -32602);
+    assert_eq!(code(&responses[2]), -32602);
+    assert_eq!(code(&responses[3]), -32602);
+    assert!(responses[4].is_none());
+
+    let (registry, args) = args_in(&temp_dir(), &["mcp"]);
+    let paths = Paths::resolve(&args);
+    let server = McpServer::new(&registry, &paths, Config::default());
+    assert_eq!(server.handle("not json").unwrap()["error"]["code"], -32700);
+}
+
+#[cfg(unix)]
+mod plugins {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    const WEATHER_SPEC: &str = r#"{
+        "about": "Weather forecasts",
+        "commands": [{
+            "name": "forecast",
+            "about": "The forecast for the next days",
+            "args": [
+                { "name": "city", "help": "The city", "required": true, "positional": true },
+                { "name": "days", "possible_values": ["1", "3", "7"] },
+                { "name": "metric", "flag": true }
+            ]
+        }]
+    }"#;
+
+    /// A plugin that prints `spec` for the handshake, and otherwise writes how it was run to `<dir>/ran`
+    fn add_plugin(bin: &Path, name: &str, spec: &str) -> PathBuf {
+        fs::create_dir_all(bin).unwrap();
+        let path = bin.join(format!("aerial-utils-{}", name));
+        let script = format!(
+            "#!/bin/sh\nif [ \"$1\" = --aerial-spec ]; then echo '{}'; exit 0; fi\necho \"$AERIAL_OUTPUT $*\" > \"$(dirname \"$0\")/ran\"\n[ \"$1\" != fail ]\n",
+            spec
+        );
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn path_var(dirs: &[&Path]) -> Option<OsString> {
+        Some(std::env::join_paths(dirs).unwrap())
+    }
+
+    #[test]
+    fn executables_with_the_prefix_are_discovered() {
+        let (first, second) = (temp_dir(), temp_dir());
+        add_plugin(&first, "weather", WEATHER_SPEC);
+        let shadowed = add_plugin(&second, "weather", WEATHER_SPEC);
+        add_plugin(&second, "notes", WEATHER_SPEC);
+        fs::set_permissions(add_plugin(&second, "not-executable", "{}"), fs::Permissions::from_mode(0o644)).unwrap();
+        fs::write(second.join("other-tool"), "").unwrap();
+
+        let plugins = Plugin::discover(path_var(&[&first, Path::new("/nonexistent"), &second]));
+        let found: Vec<(&str, &Path)> = plugins.iter().map(|plugin| (plugin.name.as_str(), plugin.path.as_path())).collect();
+        assert_eq!(
+            found,
+            [
+                ("weather", first.join("aerial-utils-weather").as_path()),
+                ("notes", second.join("aerial-utils-notes").as_path())
+            ]
+        );
+        assert!(found.iter().all(|(_, path)| *path != shadowed));
+    }
+
+    #[test]
+    fn plugin_named_like_a_command_is_ignored() {
+        let bin = temp_dir();
+        add_plugin(&bin, "music", WEATHER_SPEC);
+        add_plugin(&bin, "paths", WEATHER_SPEC);
+        let registry = ModuleRegistry::default().with_plugins(path_var(&[&bin]));
+        assert_eq!(registry.modules().map(|module| module.name.as_str()).collect::<Vec<_>>(), ["music"]);
+    }
+
+    #[test]
+    fn plugin_commands_come_from_its_spec() {
+        let bin = temp_dir();
+        add_plugin(&bin, "weather", WEATHER_SPEC);
+        let registry = ModuleRegistry::default().with_plugins(path_var(&[&bin]));
+        let command = registry.get("weather").unwrap().spec_command().unwrap();
+        assert_eq!(command.get_about().unwrap().to_string(), "Weather forecasts");
+        let forecast = command.find_subcommand("forecast").unwrap();
+        let args: Vec<(&str, Option<usize>, bool)> = forecast
+            .get_arguments()
+            .map(|arg| (arg.get_id().as_str(), arg.get_index(), arg.is_required_set()))
+            .collect();
+        assert_eq!(args, [("city", Some(1), true), ("days", None, false), ("metric", None, false)]);
+    }
+
+    #[test]
+    fn invalid_spec_is_an_error() {
+        let bin = temp_dir();
+        add_plugin(&bin, "broken", "not json");
+        let registry = ModuleRegistry::default().with_plugins(path_var(&[&bin]));
+        let result = registry.get("broken").unwrap().spec_command();
+        assert!(matches!(result, Err(AppError::PluginError(PluginError::InvalidSpec(..)))));
+    }
+
+    #[test]
+    fn plugin_gets_its_arguments_and_the_output_format() {
+        let dir = temp_dir();
+        add_plugin(&dir.join("bin"), "weather", WEATHER_SPEC);
+        run_in(&dir, &["--output", "json", "weather", "forecast", "Paris", "--days", "3"]).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("bin/ran")).unwrap(), "json forecast Paris --days 3\n");
+    }
+
+    #[test]
+    fn failing_plugin_is_an_error() {
+        let dir = temp_dir();
+        add_plugin(&dir.join("bin"), "weather", WEATHER_SPEC);
+        let result = run_in(&dir, &["weather", "fail"]);
+        assert!(matches!(result, Err(AppError::PluginError(PluginError::Failed(..)))));
+    }
+
+    #[test]
+    fn disabled_plugin_is_not_run() {
+        let dir = temp_dir();
+        add_plugin(&dir.join("bin"), "weather", WEATHER_SPEC);
+        fs::write(dir.join("config.toml"), "[modules.weather]\nenabled = false").unwrap();
+        assert!(matches!(
+            run_in(&dir, &["weather", "forecast", "Paris"]),
+            Err(AppError::ModuleDisabled(_))
+        ));
+        assert!(!dir.join("bin/ran").exists());
+    }
+
+    #[test]
+    fn mcp_tool_calls_capture_the_plugin_output() {
+        let dir = temp_dir();
+        let plugin = add_plugin(&dir.join("bin"), "weather", WEATHER_SPEC);
+        let script = fs::read_to_string(&plugin).unwrap().replace("> \"$(dirname \"$0\")/ran\"", "");
+        fs::write(&plugin, script).unwrap();
+
+        let arguments = json!({ "city": "Oslo", "days": "3", "metric": true });
+        let responses = mcp_in(&dir, &[call_tool("weather_forecast", arguments)]);
+        let result = &responses[0].as_ref().unwrap()["result"];
+        assert_eq!(result["isError"], false);
+        assert_eq!(result["content"][0]["text"], "json forecast --days 3 --metric -- Oslo");
+    }
+}
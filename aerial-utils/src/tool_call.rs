@@ -0,0 +1,166 @@
+//! Running the tool calls of LLM assistants: a call names one of the tools from `command-specs` and gives its arguments,
+//! it's checked against the tool's schema, turned back into the tool's command line and run like the CLI would run it.
+//! Used by `aerial-utils exec-tool` for a single call and by the MCP server for each `tools/call`
+
+use crate::{
+    cli::{tools_command, AerialUtilsArgs, AppError, Paths},
+    modules::{closest, did_you_mean, tools, ModuleRegistry, Tool, ToolCallError},
+    utils::{
+        output::{ErrorOutput, OutputFormat},
+        Cache, Config,
+    },
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::io::{self, Read};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ToolError {
+    #[error("Failed to read the tool call: {0}")]
+    FailedToReadCall(io::Error),
+    #[error("Invalid tool call: {0}")]
+    InvalidCall(serde_json::Error),
+    #[error("Unknown tool `{0}`{}", did_you_mean(.1))]
+    UnknownTool(String, Option<String>),
+    #[error("{0}")]
+    InvalidArguments(ToolCallError),
+    /// The command ran and failed
+    #[error("{0}")]
+    Failed(AppError),
+}
+
+impl ToolError {
+    /// A stable name for the error in the serialized output formats
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ToolError::FailedToReadCall(_) => "failed_to_read_call",
+            ToolError::InvalidCall(_) => "invalid_tool_call",
+            ToolError::UnknownTool(..) => "unknown_tool",
+            ToolError::InvalidArguments(_) => "invalid_arguments",
+            ToolError::Failed(err) => err.kind(),
+        }
+    }
+
+    /// The tool or argument that was probably meant, for a name that doesn't exist
+    pub fn suggestion(&self) -> Option<&str> {
+        match self {
+            ToolError::UnknownTool(_, suggestion) => suggestion.as_deref(),
+            ToolError::InvalidArguments(err) => err.suggestion(),
+            _ => None,
+        }
+    }
+}
+
+/// A call as the assistants make it, `{"name": "music_search", "arguments": {"query": "..."}}`.
+/// The arguments can also be a string of JSON, like OpenAI's API gives them
+#[derive(Deserialize, Debug)]
+pub struct ToolCall {
+    pub name: String,
+    #[serde(default, deserialize_with = "arguments")]
+    pub arguments: Map<String, Value>,
+}
+
+impl ToolCall {
+    /// Parses the call, reading it from stdin when it's `None` or `-`
+    pub fn read(call: Option<&str>) -> Result<Self, ToolError> {
+        let call = match call {
+            Some(call) if call != "-" => call.to_string(),
+            _ => {
+                let mut call = String::new();
+                io::stdin().read_to_string(&mut call).map_err(ToolError::FailedToReadCall)?;
+                call
+            }
+        };
+        serde_json::from_str(&call).map_err(ToolError::InvalidCall)
+    }
+}
+
+/// The arguments as an object, null or a string holding either
+fn arguments<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Map<String, Value>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Arguments {
+        Object(Map<String, Value>),
+        Encoded(String),
+        None(()),
+    }
+    match Arguments::deserialize(deserializer)? {
+        Arguments::Object(arguments) => Ok(arguments),
+        Arguments::Encoded(arguments) if arguments.trim().is_empty() => Ok(Map::new()),
+        Arguments::Encoded(arguments) => serde_json::from_str::<Option<_>>(&arguments)
+            .map(Option::unwrap_or_default)
+            .map_err(serde::de::Error::custom),
+        Arguments::None(()) => Ok(Map::new()),
+    }
+}
+
+/// Runs calls of the tools made from the commands of the enabled modules, the CLI's own commands aren't tools
+pub struct ToolRunner<'a> {
+    registry: &'a ModuleRegistry,
+    paths: &'a Paths,
+    config: Config,
+    tools: Vec<Tool>,
+}
+
+impl<'a> ToolRunner<'a> {
+    pub fn new(registry: &'a ModuleRegistry, paths: &'a Paths, config: Config) -> Self {
+        let tools = tools(tools_command(registry, &config));
+        Self {
+            registry,
+            paths,
+            config,
+            tools,
+        }
+    }
+
+    pub fn tools(&self) -> &[Tool] {
+        &self.tools
+    }
+
+    /// The command's output as JSON, like with `--output json`
+    pub fn call(&self, name: &str, arguments: &Map<String, Value>) -> Result<String, ToolError> {
+        let Some(tool) = self.tools.iter().find(|tool| tool.name == name) else {
+            let suggestion = closest(name, self.tools.iter().map(|tool| tool.name.as_str())).map(String::from);
+            return Err(ToolError::UnknownTool(name.into(), suggestion));
+        };
+        let argv = tool.argv(arguments).map_err(ToolError::InvalidArguments)?;
+        self.run(argv).map_err(ToolError::Failed)
+    }
+
+    /// Runs the command like the CLI would, with the cache read again since other commands may have changed it
+    fn run(&self, argv: Vec<String>) -> Result<String, AppError> {
+        let argv = ["aerial-utils".to_string()].into_iter().chain(argv);
+        let args = AerialUtilsArgs::try_parse_with(self.registry, argv).map_err(AppError::InvalidArguments)?;
+        // The tools are only made from the modules' commands
+        let (name, matches) = args.module.expect("A tool's command isn't a module command");
+        let module = self.registry.get(&name).ok_or_else(|| AppError::ModuleDisabled(name.clone()))?;
+
+        let mut cache = Cache::from_file(&self.paths.cache.path).map_err(AppError::CacheError)?;
+        let res = module.output(&matches, &self.config, &mut cache, self.paths, OutputFormat::Json);
+        cache.to_file(&self.paths.cache.path).map_err(AppError::CacheError)?;
+        res
+    }
+}
+
+/// What `exec-tool` prints: `{"result": ...}` with the command's output, or the error as in the serialized formats (see
+/// `ErrorOutput`) with the name that was probably meant for a misspelled tool or argument
+pub fn exec_tool(runner: &ToolRunner, call: Option<&str>) -> Value {
+    match ToolCall::read(call).and_then(|call| runner.call(&call.name, &call.arguments)) {
+        // The output of a plugin doesn't have to be JSON
+        Ok(output) => json!({ "result": serde_json::from_str(&output).unwrap_or(Value::String(output)) }),
+        Err(err) => error_output(&err),
+    }
+}
+
+/// `exec_tool` with the config read from `paths`, a config that can't be read is printed like any other failed call
+pub fn exec_tool_in(registry: &ModuleRegistry, paths: &Paths, call: Option<&str>) -> Value {
+    match Config::from_file(&paths.config.path) {
+        Ok(config) => exec_tool(&ToolRunner::new(registry, paths, config), call),
+        Err(err) => error_output(&ToolError::Failed(AppError::ConfigError(err))),
+    }
+}
+
+fn error_output(err: &ToolError) -> Value {
+    serde_json::to_value(ErrorOutput::new(err.kind(), err).with_suggestion(err.suggestion())).unwrap()
+}
@@ -1,6 +1,31 @@
-use super::{http::ResponseError, ApiRequestSpec};
+use super::{
+    http::ResponseError,
+    paging::{PageLink, Paging},
+    ApiRequestSpec,
+};
 use serde::{de::DeserializeOwned, Serialize};
 
 pub trait ApiHandler {
     fn make_request<B: Serialize, R: DeserializeOwned>(&self, spec: &dyn ApiRequestSpec<Body = B, Resposne = R>) -> Result<R, ResponseError>;
+
+    /// Collects the items of `first_page` and of the pages after it, up to `max_items`.
+    /// `get_page` takes the paging object out of the endpoint's response, for endpoints that wrap it (like search)
+    fn collect_pages<R: DeserializeOwned, T>(
+        &self,
+        first_page: Paging<T>,
+        max_items: usize,
+        get_page: impl Fn(R) -> Option<Paging<T>>,
+    ) -> Result<Vec<T>, ResponseError> {
+        let mut items = first_page.items;
+        let mut next = first_page.next;
+        while let Some(url) = next.filter(|_| items.len() < max_items) {
+            let Some(page) = get_page(self.make_request(&PageLink::<R>::new(url))?) else {
+                break;
+            };
+            items.extend(page.items);
+            next = page.next;
+        }
+        items.truncate(max_items);
+        Ok(items)
+    }
 }
@@ -1,6 +1,19 @@
-use reqwest::{blocking::RequestBuilder, header::HeaderMap, Method};
+use reqwest::{
+    blocking::{Client, RequestBuilder},
+    header::HeaderMap,
+    Method,
+};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RequestError {
+    #[error("Invalid request URL `{0}`: {1}")]
+    InvalidUrl(String, url::ParseError),
+    #[error("Not following `{0}`, it isn't a URL of the API at `{1}`")]
+    ForeignUrl(String, String),
+}
 
 pub trait ApiRequestSpec {
     type Resposne: DeserializeOwned;
@@ -9,25 +22,47 @@ pub trait ApiRequestSpec {
     // TODO: Change this to take self instead of a reference
     fn request(&self) -> ApiRequest<Self::Body>;
 
-    fn build(&self, api_endpoint: &str) -> RequestBuilder {
+    /// Whether sending the request more than once has the same effect as sending it once, so it can be retried after
+    /// a failure, override this to mark requests with non-idempotent methods that are still safe
+    fn retry_safe(&self) -> bool {
+        self.request().method.is_idempotent()
+    }
+
+    /// Full URLs (like the links to the next page) are only requested when they're under `api_endpoint`, since the
+    /// request is sent with the API's token
+    fn build(&self, client: &Client, api_endpoint: &str) -> Result<RequestBuilder, RequestError> {
         let request = self.request();
-        let endpoint = format!("{}/{}", api_endpoint, request.endpoint);
-        // TODO: Remove these unwraps
-        let url = reqwest::Url::parse_with_params(endpoint.as_str(), request.params.unwrap_or_default()).unwrap();
+        let endpoint = if request.endpoint.starts_with("http://") || request.endpoint.starts_with("https://") {
+            if !is_under(&request.endpoint, api_endpoint) {
+                return Err(RequestError::ForeignUrl(request.endpoint, api_endpoint.into()));
+            }
+            request.endpoint
+        } else {
+            format!("{}/{}", api_endpoint, request.endpoint)
+        };
+        let url = reqwest::Url::parse_with_params(endpoint.as_str(), request.params.unwrap_or_default())
+            .map_err(|err| RequestError::InvalidUrl(endpoint.clone(), err))?;
 
-        let mut req = reqwest::blocking::Client::new()
-            .request(request.method, url)
-            .headers(request.headers.unwrap_or(HeaderMap::new()));
+        let mut req = client.request(request.method, url).headers(request.headers.unwrap_or_default());
 
         if let Some(body) = request.body {
+            // Bodies are plain structs, so serializing them can't fail
             req = req.body(serde_json::to_string_pretty(&body).unwrap());
         }
-        return req;
+        Ok(req)
     }
 }
 
+/// Whether the URL is the API's URL or one of the paths under it, the prefix alone would also match other hosts and paths
+/// (like `https://api.example.com.evil` or `/v10`)
+fn is_under(url: &str, api_endpoint: &str) -> bool {
+    url.strip_prefix(api_endpoint.trim_end_matches('/'))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', '?']))
+}
+
 pub struct ApiRequest<T: Serialize + Sized> {
     pub method: Method,
+    /// Relative to the API's endpoint, or a full URL (like a paging link)
     pub endpoint: String,
     pub headers: Option<HeaderMap>,
     pub params: Option<HashMap<String, String>>,
@@ -82,40 +117,42 @@ impl<T> From<OptionalResponse<T>> for Option<T> {
 #[derive(Serialize)]
 pub struct NoBody {}
 
+/// Implements `ApiRequestSpec` for a request type, from its method, endpoint and response type, and optionally a function
+/// making its params (`endpoint => params_func`) or its body (`body_func => Body`) out of it
 #[macro_export]
 macro_rules! impl_endpoint {
     // Implement a request without body or params
     ($spec:ident, $method:path, $endpoint:expr, $response:ident) => {
-        impl ApiRequestSpec for $spec {
+        impl $crate::utils::ApiRequestSpec for $spec {
             type Resposne = $response;
-            type Body = NoBody;
+            type Body = $crate::utils::api_spec::NoBody;
 
-            fn request(&self) -> ApiRequest<Self::Body> {
-                ApiRequest::basic($method, $endpoint)
+            fn request(&self) -> $crate::utils::ApiRequest<Self::Body> {
+                $crate::utils::ApiRequest::basic($method, $endpoint)
             }
         }
     };
     // Implement a request with params
     ($spec:ident, $method:path, $endpoint:expr => $params_func:ident, $response:ident) => {
-        impl ApiRequestSpec for $spec {
+        impl $crate::utils::ApiRequestSpec for $spec {
             type Resposne = $response;
-            type Body = NoBody;
+            type Body = $crate::utils::api_spec::NoBody;
 
-            fn request(&self) -> ApiRequest<Self::Body> {
+            fn request(&self) -> $crate::utils::ApiRequest<Self::Body> {
                 let params = $params_func(self);
-                ApiRequest::basic_with_params($method, $endpoint, Some(params))
+                $crate::utils::ApiRequest::basic_with_params($method, $endpoint, Some(params))
             }
         }
     };
     // Implement a request with a body
     ($spec:ident, $method:path, $endpoint:expr, $body_func:ident => $body:ident, $response:ident) => {
-        impl ApiRequestSpec for $spec {
+        impl $crate::utils::ApiRequestSpec for $spec {
             type Resposne = $response;
             type Body = $body;
 
-            fn request(&self) -> ApiRequest<Self::Body> {
+            fn request(&self) -> $crate::utils::ApiRequest<Self::Body> {
                 let body = $body_func(self);
-                ApiRequest::basic_with_body($method, $endpoint, body)
+                $crate::utils::ApiRequest::basic_with_body($method, $endpoint, body)
             }
         }
     };
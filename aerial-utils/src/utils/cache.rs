@@ -1,50 +1,246 @@
 use crate::modules::music::Token;
-use serde::{Deserialize, Serialize};
+use crate::utils::config::DEFAULT_PROFILE;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::default::Default;
-use std::fs::{self, File};
+use std::fs::{self, OpenOptions};
 use std::io::Write;
-use toml::de;
+use std::path::{Path, PathBuf};
+use toml::{de, Table};
 
 #[derive(Serialize, Deserialize, Default)]
 pub struct Cache {
+    #[serde(default)]
     pub modules: ModulesCache,
+    /// The modules' caches as they were read, to only write back the sections this process changed
+    #[serde(skip)]
+    loaded: Table,
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(thiserror::Error, Debug)]
 pub enum CacheError {
     #[error("Invalid cache file: {0}")]
     FailedToParseToml(de::Error),
+    #[error("Invalid `[modules.{0}]` section in the cache file: {1}")]
+    FailedToParseSection(&'static str, de::Error),
     #[error("Failed to open file to write to, path: {0}")]
     FailedToWriteFile(std::io::Error),
     #[error("Failed to convert cache to string for writing: {0}")]
     FailedToPrintCache(toml::ser::Error),
+    #[error("Failed to lock the cache file: {0}")]
+    FailedToLockFile(std::io::Error),
 }
 
 impl Cache {
-    pub fn from_file(path: &str) -> Result<Self, CacheError> {
+    /// Doesn't need the lock, since the file is only ever replaced as a whole (see `to_file`).
+    /// A file others can read (like one written before the cache was private) is made private right away, not only once
+    /// it's written again
+    pub fn from_file(path: &Path) -> Result<Self, CacheError> {
         match fs::read_to_string(path) {
-            Ok(raw_cache) => toml::from_str(&raw_cache).map_err(CacheError::FailedToParseToml),
+            Ok(raw_cache) => {
+                make_private(path);
+                let mut cache: Self = toml::from_str(&raw_cache).map_err(CacheError::FailedToParseToml)?;
+                cache.loaded = cache.modules.0.clone();
+                Ok(cache)
+            }
             Err(err) => {
-                eprintln!("WARNING: Could not read cache file from `{}`: {}", path, err);
+                eprintln!("WARNING: Could not read cache file from `{}`: {}", path.display(), err);
                 Ok(Self::default())
             }
         }
     }
 
-    pub fn to_file(&self, path: &str) -> Result<(), CacheError> {
-        let mut file = File::create(path).map_err(CacheError::FailedToWriteFile)?;
-        let cache = toml::to_string(self).map_err(CacheError::FailedToPrintCache)?;
+    /// Writes the sections that changed since the cache was read into the file as it is now, so commands running at the
+    /// same time don't undo each other's changes (like a refreshed token). A section is a table with values of its own,
+    /// tables that only hold other tables (like the profiles of a module) are merged key by key.
+    /// The merge happens under a lock on `<file>.lock`, and the file is replaced by renaming a fully written temporary file
+    /// to it, so it's never seen half written. Only the owner can read it, since it holds refresh tokens.
+    /// Creates the file's directory if needed, since the default location is in a directory of its own
+    pub fn to_file(&self, path: &Path) -> Result<(), CacheError> {
+        let mut changed = Vec::new();
+        changed_sections(&mut vec!["modules".into()], &self.modules.0, &self.loaded, &mut changed);
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(CacheError::FailedToWriteFile)?;
+        }
+        let lock = private_file()
+            .truncate(false)
+            .open(with_suffix(path, ".lock"))
+            .map_err(CacheError::FailedToLockFile)?;
+        lock.lock().map_err(CacheError::FailedToLockFile)?;
+
+        let mut current = read_table(path);
+        for (section_path, section) in changed {
+            set_section(&mut current, &section_path, section);
+        }
+        let cache = toml::to_string(&current).map_err(CacheError::FailedToPrintCache)?;
+
+        let temp_path = with_suffix(path, &format!(".{}.tmp", std::process::id()));
+        let mut file = private_file().truncate(true).open(&temp_path).map_err(CacheError::FailedToWriteFile)?;
         file.write_all(&cache.into_bytes()).map_err(CacheError::FailedToWriteFile)?;
-        Ok(())
+        file.sync_all().map_err(CacheError::FailedToWriteFile)?;
+        fs::rename(&temp_path, path).map_err(CacheError::FailedToWriteFile)?;
+        // Dropping the file unlocks it as well, this only makes the order explicit
+        lock.unlock().map_err(CacheError::FailedToLockFile)
+    }
+}
+
+/// The paths of the sections in `new` that differ from `old`, with their new value (`None` if they were removed)
+fn changed_sections(path: &mut Vec<String>, new: &Table, old: &Table, changed: &mut Vec<(Vec<String>, Option<toml::Value>)>) {
+    let empty = Table::new();
+    let mut names: Vec<&String> = new.keys().chain(old.keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let (new_value, old_value) = (new.get(name), old.get(name));
+        if new_value == old_value {
+            continue;
+        }
+        path.push(name.clone());
+        match (new_value.map_or(Some(&empty), tables_only), old_value.map_or(Some(&empty), tables_only)) {
+            (Some(new_table), Some(old_table)) => changed_sections(path, new_table, old_table, changed),
+            _ => changed.push((path.clone(), new_value.cloned())),
+        }
+        path.pop();
+    }
+}
+
+/// The value as a table if it only holds other tables
+fn tables_only(value: &toml::Value) -> Option<&Table> {
+    value.as_table().filter(|table| table.values().all(toml::Value::is_table))
+}
+
+/// Replaces or removes the section at the path, creating the tables above it if needed
+fn set_section(table: &mut Table, path: &[String], section: Option<toml::Value>) {
+    let [parents @ .., name] = path else {
+        return;
+    };
+    let mut table = table;
+    for parent in parents {
+        let value = table.entry(parent.clone()).or_insert_with(|| Table::new().into());
+        if !value.is_table() {
+            *value = Table::new().into();
+        }
+        let toml::Value::Table(parent) = value else { unreachable!() };
+        table = parent;
+    }
+    match section {
+        Some(section) => table.insert(name.clone(), section),
+        None => table.remove(name),
+    };
+}
+
+/// The file as it is on disk, a file that can't be read or isn't a valid cache anymore is replaced
+fn read_table(path: &Path) -> Table {
+    let Ok(raw_cache) = fs::read_to_string(path) else {
+        return Table::new();
+    };
+    match raw_cache.parse::<Table>() {
+        Ok(table) if table.get("modules").is_none_or(toml::Value::is_table) => table,
+        _ => {
+            eprintln!("WARNING: Replacing invalid cache file `{}`", path.display());
+            Table::new()
+        }
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+/// Options to create a file only the owner can read and write
+fn private_file() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+}
+
+#[cfg(unix)]
+fn make_private(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    let Ok(metadata) = fs::metadata(path) else {
+        return;
+    };
+    if metadata.permissions().mode() & 0o077 != 0 {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+            .unwrap_or_else(|err| eprintln!("WARNING: Could not make the cache file `{}` private: {}", path.display(), err));
     }
 }
 
+#[cfg(not(unix))]
+fn make_private(_: &Path) {}
+
+/// The `[modules.<name>]` sections of the cache, each belonging to a module
 #[derive(Serialize, Deserialize, Default)]
-pub struct ModulesCache {
-    pub spotify: Option<SpotifyCache>,
+#[serde(transparent)]
+pub struct ModulesCache(Table);
+
+/// A module's section of the cache, an empty section isn't written
+pub trait CacheSection: Serialize + DeserializeOwned + Default {
+    /// The name of the section under `[modules]`
+    const NAME: &'static str;
+}
+
+impl ModulesCache {
+    /// The parsed section, empty if the cache doesn't have it
+    pub fn section<T: CacheSection>(&self) -> Result<T, CacheError> {
+        match self.0.get(T::NAME) {
+            Some(section) => section.clone().try_into().map_err(|err| CacheError::FailedToParseSection(T::NAME, err)),
+            None => Ok(T::default()),
+        }
+    }
+
+    pub fn set_section<T: CacheSection>(&mut self, section: &T) -> Result<(), CacheError> {
+        let section = Table::try_from(section).map_err(CacheError::FailedToPrintCache)?;
+        if section.is_empty() {
+            self.0.remove(T::NAME);
+        } else {
+            self.0.insert(T::NAME.into(), section.into());
+        }
+        Ok(())
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct SpotifyCache {
-    pub token: Token,
+    /// The token of the default profile, kept where it was before there were profiles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<Token>,
+    /// The tokens of the other profiles
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Token>,
+}
+
+impl CacheSection for SpotifyCache {
+    const NAME: &'static str = "spotify";
+}
+
+impl SpotifyCache {
+    pub fn get_token(&self, profile: &str) -> Option<&Token> {
+        match profile {
+            DEFAULT_PROFILE => self.token.as_ref(),
+            _ => self.profiles.get(profile),
+        }
+    }
+
+    /// Removes the profile's token when given `None`
+    pub fn set_token(&mut self, profile: &str, token: Option<Token>) {
+        match (profile, token) {
+            (DEFAULT_PROFILE, token) => self.token = token,
+            (_, Some(token)) => {
+                self.profiles.insert(profile.into(), token);
+            }
+            (_, None) => {
+                self.profiles.remove(profile);
+            }
+        }
+    }
 }
@@ -1,10 +1,21 @@
-use serde::Deserialize;
+use super::{
+    http::HttpConfig,
+    retry::RetryPolicy,
+    secret::{resolve_secret, SecretError},
+};
+use serde::{de::DeserializeOwned, Deserialize};
 use std::default::Default;
-use std::fs;
-use toml::de;
+use std::{collections::BTreeMap, env, fs, path::Path};
+use toml::{de, Table};
+
+const SPOTIFY_API_URL_ENV: &str = "AERIAL_SPOTIFY_API_URL";
+const SPOTIFY_ACCOUNTS_URL_ENV: &str = "AERIAL_SPOTIFY_ACCOUNTS_URL";
+/// The profile made of the credentials at the top of the Spotify config
+pub const DEFAULT_PROFILE: &str = "default";
 
 #[derive(Deserialize, Default)]
 pub struct Config {
+    #[serde(default)]
     pub modules: ModulesConfig,
 }
 
@@ -12,28 +23,204 @@ pub struct Config {
 pub enum ConfigError {
     #[error("Failed to parse config file: {0}")]
     FailedToParseToml(de::Error),
+    #[error("Invalid `[modules.{0}]` section in the config file: {1}")]
+    FailedToParseSection(&'static str, de::Error),
+    #[error("Failed to get the value of `{0}`: {1}")]
+    FailedToResolveSecret(String, SecretError),
 }
 
 impl Config {
-    pub fn from_file(path: &str) -> Result<Self, ConfigError> {
+    /// The modules' sections are only parsed when they're used, see `ModulesConfig::section`.
+    /// Secrets (`env:`, `file:` and `cmd:` values) aren't resolved here either but for the profile a command uses (see
+    /// `SpotifyConfig::with_profile`), so a broken secret only fails the commands that need it and commands only run
+    /// the secret commands they need
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
         match fs::read_to_string(path) {
             Ok(raw_config) => toml::from_str(&raw_config).map_err(ConfigError::FailedToParseToml),
             Err(err) => {
-                eprintln!("WARNING: Could not read config file from `{}`: {}", path, err);
+                eprintln!("WARNING: Could not read config file from `{}`: {}", path.display(), err);
                 Ok(Self::default())
             }
         }
     }
 }
 
+/// The `[modules.<name>]` sections of the config, each belonging to a module
 #[derive(Deserialize, Default)]
-pub struct ModulesConfig {
-    pub spotify: Option<SpotifyConfig>,
+#[serde(transparent)]
+pub struct ModulesConfig(Table);
+
+/// A module's section of the config
+pub trait ConfigSection: DeserializeOwned {
+    /// The name of the section under `[modules]`
+    const NAME: &'static str;
+
+    /// Finishes the values after they're parsed, like applying env overrides
+    fn load(&mut self) -> Result<(), ConfigError> {
+        Ok(())
+    }
 }
 
-#[derive(Deserialize)]
+impl ModulesConfig {
+    /// The parsed section, `None` if the config doesn't have it.
+    /// Values that come from outside the file (like env overrides) are only applied to the sections that are used
+    pub fn section<T: ConfigSection>(&self) -> Result<Option<T>, ConfigError> {
+        let Some(section) = self.0.get(T::NAME) else {
+            return Ok(None);
+        };
+        let mut section: T = section
+            .clone()
+            .try_into()
+            .map_err(|err| ConfigError::FailedToParseSection(T::NAME, err))?;
+        section.load()?;
+        Ok(Some(section))
+    }
+
+    /// Whether the section doesn't turn its module off with `enabled = false`
+    pub fn is_enabled(&self, name: &str) -> bool {
+        let enabled = self.0.get(name).and_then(|section| section.get("enabled"));
+        enabled.and_then(toml::Value::as_bool).unwrap_or(true)
+    }
+}
+
+#[derive(Deserialize, Clone)]
 pub struct SpotifyConfig {
-    // TODO: Add option to not set these in the config and give them at runtime instead
+    /// Like the secret, can be read from elsewhere with `env:`, `file:` or `cmd:` (see `resolve_secret`).
+    /// Both are only resolved for the profile in use, by `with_profile`
     pub client_id: String,
-    pub client_secret: String,
+    /// Leave unset to authenticate with PKCE, which only needs the client id
+    pub client_secret: Option<String>,
+    /// Ports tried in order for the authorization redirect, each needs its redirect URI registered in the Spotify app
+    #[serde(default = "default_redirect_ports")]
+    pub redirect_ports: Vec<u16>,
+    /// How long to wait for the authorization redirect
+    #[serde(default = "default_auth_timeout_secs")]
+    pub auth_timeout_secs: u64,
+    /// Base URL of the Web API, can be overridden by `AERIAL_SPOTIFY_API_URL` (e.g. to point at a local mock)
+    #[serde(default = "default_spotify_api_url")]
+    pub api_url: String,
+    /// Base URL of the accounts service used for authorization and tokens, can be overridden by `AERIAL_SPOTIFY_ACCOUNTS_URL`
+    #[serde(default = "default_spotify_accounts_url")]
+    pub accounts_url: String,
+    /// How failed API requests are retried
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub http: HttpConfig,
+    /// Tokens are refreshed this many seconds before they expire, so they don't expire mid-command
+    #[serde(default = "default_refresh_margin_secs")]
+    pub refresh_margin_secs: u64,
+    /// The most items fetched when getting all the pages of a list (e.g. `search --all`)
+    #[serde(default = "default_max_paged_items")]
+    pub max_paged_items: usize,
+    /// The profile used when none is given with `--profile`
+    #[serde(default = "default_profile")]
+    pub default_profile: String,
+    /// Other Spotify accounts, each with a token of its own in the cache
+    #[serde(default)]
+    pub profiles: BTreeMap<String, SpotifyProfileConfig>,
+    /// The profile the credentials above are for, see `with_profile`
+    #[serde(skip_deserializing, default = "default_profile")]
+    pub profile: String,
+}
+
+/// An account's credentials. Without a client id it's an account of the app at the top of the Spotify config, and uses
+/// its secret unless it sets its own (an app can be authorized by several accounts). With a client id it's another app,
+/// and only uses its own secret, or PKCE without one
+#[derive(Deserialize, Clone, Default)]
+pub struct SpotifyProfileConfig {
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+impl ConfigSection for SpotifyConfig {
+    const NAME: &'static str = "spotify";
+
+    fn load(&mut self) -> Result<(), ConfigError> {
+        self.apply_env_overrides();
+        Ok(())
+    }
+}
+
+impl SpotifyConfig {
+    fn apply_env_overrides(&mut self) {
+        if let Ok(api_url) = env::var(SPOTIFY_API_URL_ENV) {
+            self.api_url = api_url;
+        }
+        if let Ok(accounts_url) = env::var(SPOTIFY_ACCOUNTS_URL_ENV) {
+            self.accounts_url = accounts_url;
+        }
+        self.api_url = self.api_url.trim_end_matches('/').into();
+        self.accounts_url = self.accounts_url.trim_end_matches('/').into();
+    }
+
+    /// The names of all profiles, the default one first
+    pub fn profile_names(&self) -> Vec<&str> {
+        let mut names = vec![DEFAULT_PROFILE];
+        names.extend(self.profiles.keys().map(String::as_str).filter(|name| *name != DEFAULT_PROFILE));
+        names
+    }
+
+    /// The config with the credentials of the given profile, or of `default_profile` if none is given, with their secrets
+    /// resolved. Only this profile's secrets are resolved, so the other profiles' can't fail and their commands aren't run.
+    /// `None` if there is no such profile
+    pub fn with_profile(&self, name: Option<&str>) -> Result<Option<SpotifyConfig>, ConfigError> {
+        let name = name.unwrap_or(&self.default_profile);
+        let profile = match self.profiles.get(name) {
+            Some(profile) => profile.clone(),
+            None if name == DEFAULT_PROFILE => SpotifyProfileConfig::default(),
+            None => return Ok(None),
+        };
+        let field = |key: &str| format!("modules.spotify.profiles.{}.{}", name, key);
+
+        let mut config = self.clone();
+        config.profile = name.into();
+        config.client_id = match &profile.client_id {
+            Some(client_id) => resolve_field(&field("client_id"), client_id)?,
+            None => resolve_field("modules.spotify.client_id", &self.client_id)?,
+        };
+        // The secret at the top belongs to the client id at the top, sending it with another app's id would be rejected
+        config.client_secret = match (&profile.client_secret, &profile.client_id) {
+            (Some(client_secret), _) => Some(resolve_field(&field("client_secret"), client_secret)?),
+            (None, Some(_)) => None,
+            (None, None) => resolve_optional_field("modules.spotify.client_secret", &self.client_secret)?,
+        };
+        Ok(Some(config))
+    }
+}
+
+fn resolve_field(field: &str, value: &str) -> Result<String, ConfigError> {
+    resolve_secret(value).map_err(|err| ConfigError::FailedToResolveSecret(field.into(), err))
+}
+
+fn resolve_optional_field(field: &str, value: &Option<String>) -> Result<Option<String>, ConfigError> {
+    value.as_deref().map(|value| resolve_field(field, value)).transpose()
+}
+
+fn default_profile() -> String {
+    DEFAULT_PROFILE.into()
+}
+
+fn default_redirect_ports() -> Vec<u16> {
+    vec![8888, 8889, 8890]
+}
+
+fn default_auth_timeout_secs() -> u64 {
+    300
+}
+
+fn default_refresh_margin_secs() -> u64 {
+    60
+}
+
+fn default_max_paged_items() -> usize {
+    200
+}
+
+fn default_spotify_api_url() -> String {
+    "https://api.spotify.com/v1".into()
+}
+
+fn default_spotify_accounts_url() -> String {
+    "https://accounts.spotify.com".into()
 }
@@ -1,13 +1,63 @@
-use reqwest::{blocking::Response, StatusCode};
-use serde::de::DeserializeOwned;
+use super::api_spec::RequestError;
+use reqwest::{
+    blocking::{Client, Response},
+    Proxy, StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::time::Duration;
 use thiserror::Error;
 
+/// How the HTTP client a module sends all of its requests with is set up.
+/// The client is shared by all of a command's requests, so they reuse its connections instead of each opening their own
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// The time limit of a whole request, from connecting until the response body is read
+    pub timeout_secs: u64,
+    pub connect_timeout_secs: u64,
+    /// Idle connections are kept open this long for reuse
+    pub pool_idle_timeout_secs: u64,
+    /// A proxy for all requests, like `http://localhost:8080`. When unset the standard proxy environment variables
+    /// (`HTTPS_PROXY` etc.) are used
+    pub proxy: Option<String>,
+    pub user_agent: String,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 30,
+            connect_timeout_secs: 10,
+            pool_idle_timeout_secs: 90,
+            proxy: None,
+            user_agent: concat!("aerial-utils/", env!("CARGO_PKG_VERSION")).into(),
+        }
+    }
+}
+
+impl HttpConfig {
+    /// Only fails if the config is invalid, like a proxy URL that can't be parsed
+    pub fn build_client(&self) -> Result<Client, reqwest::Error> {
+        let mut builder = Client::builder()
+            .timeout(Duration::from_secs(self.timeout_secs))
+            .connect_timeout(Duration::from_secs(self.connect_timeout_secs))
+            .pool_idle_timeout(Duration::from_secs(self.pool_idle_timeout_secs))
+            .user_agent(self.user_agent.as_str());
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+        builder.build()
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum ResponseError {
     #[error("Got an invalid resposne: {0}")]
     InvalidResposne(ResponseValidationError),
     #[error("Couldn't extract data from response: {0}")]
     InvalidExtraction(ResponseExtractionError),
+    #[error("Couldn't make the request: {0}")]
+    InvalidRequest(RequestError),
 }
 
 #[derive(Error, Debug)]
@@ -1,3 +1,5 @@
+//! Building blocks shared by the modules: web API requests and their handling, OAuth, and the config and cache files
+
 pub mod api_handler;
 #[macro_use]
 pub mod api_spec;
@@ -5,9 +7,18 @@ pub mod auth_client;
 pub mod cache;
 pub mod config;
 pub mod http;
+pub mod oauth;
+pub mod output;
+pub mod paging;
+pub mod paths;
+pub mod retry;
+pub mod secret;
 pub mod server;
 
 pub use api_spec::{ApiRequest, ApiRequestSpec};
 pub use auth_client::AuthClient;
 pub use cache::Cache;
 pub use config::Config;
+
+#[cfg(test)]
+mod tests;
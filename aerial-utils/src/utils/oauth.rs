@@ -0,0 +1,27 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as base64_url_engine, Engine as _};
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+
+const CODE_VERIFIER_LENGTH: usize = 64;
+
+/// A PKCE (RFC 7636) verifier and its matching challenge, the verifier must be kept until the code is exchanged for a token
+pub struct PkceChallenge {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+impl PkceChallenge {
+    pub fn generate() -> Self {
+        let verifier = random_string(CODE_VERIFIER_LENGTH);
+        let challenge = base64_url_engine.encode(Sha256::digest(verifier.as_bytes()));
+        Self { verifier, challenge }
+    }
+
+    pub fn method(&self) -> &'static str {
+        "S256"
+    }
+}
+
+pub fn random_string(length: usize) -> String {
+    rand::thread_rng().sample_iter(&Alphanumeric).take(length).map(char::from).collect()
+}
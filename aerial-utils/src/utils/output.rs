@@ -0,0 +1,109 @@
+use serde::{Serialize, Serializer};
+use std::fmt::Display;
+
+/// How command results and errors are printed, the serialized formats are meant for scripts and the AI.
+/// Results are printed to stdout, and so are errors in the serialized formats (like `exec-tool` and the MCP server return
+/// them), a script reads either from the same place. Errors as text go to stderr
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+    Yaml,
+}
+
+impl OutputFormat {
+    /// The value as shown by its `Display` impl for text, serialized otherwise
+    pub fn render<T: Serialize + Display>(&self, value: &T) -> String {
+        // Output types are plain structs and lists, so serializing them can't fail
+        match self {
+            OutputFormat::Text => value.to_string(),
+            OutputFormat::Json => serde_json::to_string_pretty(value).unwrap(),
+            OutputFormat::Yaml => serde_yaml::to_string(value).unwrap().trim_end().into(),
+        }
+    }
+
+    pub fn print<T: Serialize + Display>(&self, value: &T) {
+        Self::print_rendered(&self.render(value));
+    }
+
+    pub fn print_rendered(output: &str) {
+        // Commands without a result print nothing as text
+        if !output.is_empty() {
+            println!("{}", output);
+        }
+    }
+}
+
+/// The result of commands that only perform an action, `{"status": "ok"}` when serialized
+pub struct Done;
+
+impl Serialize for Done {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Status {
+            status: &'static str,
+        }
+        Status { status: "ok" }.serialize(serializer)
+    }
+}
+
+impl Display for Done {
+    fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Ok(())
+    }
+}
+
+/// A list of results, serialized as an array and shown as text with a blank line between the items
+#[derive(Serialize)]
+#[serde(transparent)]
+pub struct List<T>(pub Vec<T>);
+
+impl<T: Display> Display for List<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "No results returned");
+        }
+        write!(f, "{}", self.0.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n\n"))
+    }
+}
+
+/// An error as printed in the serialized formats: `{"error": {"kind": "...", "message": "..."}}`.
+/// `kind` is a stable snake_case name scripts can match on, `message` is the same text shown in text mode.
+/// Errors about a misspelled name also have the name that was probably meant as `suggestion`
+#[derive(Serialize)]
+pub struct ErrorOutput {
+    error: ErrorDetails,
+}
+
+#[derive(Serialize)]
+struct ErrorDetails {
+    kind: String,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suggestion: Option<String>,
+}
+
+impl ErrorOutput {
+    pub fn new(kind: &str, err: &impl Display) -> Self {
+        Self {
+            error: ErrorDetails {
+                kind: kind.into(),
+                message: err.to_string(),
+                suggestion: None,
+            },
+        }
+    }
+
+    pub fn with_suggestion(mut self, suggestion: Option<&str>) -> Self {
+        self.error.suggestion = suggestion.map(String::from);
+        self
+    }
+}
+
+impl Display for ErrorOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error.message)
+    }
+}
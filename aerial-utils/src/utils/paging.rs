@@ -0,0 +1,42 @@
+use super::api_spec::{ApiRequest, ApiRequestSpec, NoBody};
+use reqwest::Method;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use std::marker::PhantomData;
+
+/// A page of a list endpoint, `next` is the full URL of the page after it
+#[derive(Deserialize)]
+pub struct Paging<T> {
+    #[serde(deserialize_with = "deserialize_vec_skip_null", bound(deserialize = "T: Deserialize<'de>"))]
+    pub items: Vec<T>,
+    pub next: Option<String>,
+}
+
+/// A request for the page a `next` link points to, `R` is the response type of the paged endpoint
+pub struct PageLink<R> {
+    pub url: String,
+    response: PhantomData<R>,
+}
+
+impl<R> PageLink<R> {
+    pub fn new(url: String) -> Self {
+        Self { url, response: PhantomData }
+    }
+}
+
+impl<R: DeserializeOwned> ApiRequestSpec for PageLink<R> {
+    type Resposne = R;
+    type Body = NoBody;
+
+    fn request(&self) -> ApiRequest<Self::Body> {
+        ApiRequest::basic(Method::GET, &self.url)
+    }
+}
+
+pub fn deserialize_vec_skip_null<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    let opt_vec: Option<Vec<Option<T>>> = Deserialize::deserialize(deserializer)?;
+    Ok(opt_vec.unwrap_or_default().into_iter().flatten().collect())
+}
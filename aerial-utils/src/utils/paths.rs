@@ -0,0 +1,121 @@
+//! Where the config and cache files are, so the CLI finds the same files wherever it's run from
+
+use serde::Serialize;
+use std::{
+    env,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+const APP_DIR: &str = "aerial";
+
+pub const CONFIG_FILE: FileLocation = FileLocation {
+    file_name: "config.toml",
+    env: "AERIAL_CONFIG",
+    xdg_env: "XDG_CONFIG_HOME",
+    xdg_default: ".config",
+};
+
+pub const CACHE_FILE: FileLocation = FileLocation {
+    file_name: "cache.toml",
+    env: "AERIAL_CACHE",
+    xdg_env: "XDG_CACHE_HOME",
+    xdg_default: ".cache",
+};
+
+/// How to find one of the files, see `resolve` for the lookup order
+pub struct FileLocation {
+    file_name: &'static str,
+    /// Overrides the path of the file
+    env: &'static str,
+    /// The XDG base directory variable of the file's directory
+    xdg_env: &'static str,
+    /// The base directory under the home directory when the XDG variable isn't set
+    xdg_default: &'static str,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PathSource {
+    Flag,
+    Env,
+    Xdg,
+    /// The working directory, where the files were before they had a fixed location
+    Legacy,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ResolvedPath {
+    pub path: PathBuf,
+    pub source: PathSource,
+    pub exists: bool,
+}
+
+impl FileLocation {
+    /// The first of: the given path (from a flag), the path in the file's environment variable, the file in its XDG base
+    /// directory if it exists, and the file in the working directory if it exists.
+    /// If neither file exists the XDG one is used, so new files are created there
+    pub fn resolve(&self, flag: Option<PathBuf>) -> ResolvedPath {
+        self.resolve_in(flag, |key| env::var(key).ok(), Path::new("."))
+    }
+
+    pub(crate) fn resolve_in(&self, flag: Option<PathBuf>, env: impl Fn(&str) -> Option<String>, working_dir: &Path) -> ResolvedPath {
+        if let Some(path) = flag {
+            return ResolvedPath::new(path, PathSource::Flag);
+        }
+        if let Some(path) = env(self.env).filter(|path| !path.is_empty()) {
+            return ResolvedPath::new(path.into(), PathSource::Env);
+        }
+
+        let xdg_path = self.xdg_dir(&env).map(|dir| dir.join(APP_DIR).join(self.file_name));
+        let legacy_path = working_dir.join(self.file_name);
+        match xdg_path {
+            Some(xdg_path) if xdg_path.exists() || !legacy_path.exists() => ResolvedPath::new(xdg_path, PathSource::Xdg),
+            _ => ResolvedPath::new(legacy_path, PathSource::Legacy),
+        }
+    }
+
+    /// The environment variable that overrides the file's path
+    pub fn env_var(&self) -> &'static str {
+        self.env
+    }
+
+    /// The XDG spec says relative paths in its variables are invalid and should be ignored
+    fn xdg_dir(&self, env: &impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+        match env(self.xdg_env).map(PathBuf::from) {
+            Some(dir) if dir.is_absolute() => Some(dir),
+            _ => env("HOME")
+                .filter(|home| !home.is_empty())
+                .map(|home| Path::new(&home).join(self.xdg_default)),
+        }
+    }
+}
+
+impl ResolvedPath {
+    fn new(path: PathBuf, source: PathSource) -> Self {
+        let exists = path.exists();
+        Self { path, source, exists }
+    }
+}
+
+impl Display for PathSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            PathSource::Flag => "given as a flag",
+            PathSource::Env => "given in an environment variable",
+            PathSource::Xdg => "XDG base directory",
+            PathSource::Legacy => "legacy location in the working directory",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+impl Display for ResolvedPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}", self.path.display(), self.source)?;
+        if !self.exists {
+            write!(f, ", doesn't exist yet")?;
+        }
+        write!(f, ")")
+    }
+}
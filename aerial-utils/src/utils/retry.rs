@@ -0,0 +1,61 @@
+use rand::Rng;
+use reqwest::{blocking::Response, header::RETRY_AFTER, StatusCode};
+use serde::Deserialize;
+use std::time::Duration;
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// How many times a request is retried before its error is returned
+    pub max_retries: u32,
+    /// The delay before the first retry, doubled on every retry after it
+    pub base_delay_ms: u64,
+    /// The longest delay between retries, the doubling stops there
+    pub max_delay_ms: u64,
+    /// A rate limited request is only retried if the server asks to wait at most this long
+    pub max_retry_after_secs: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay_ms: 500,
+            max_delay_ms: 8000,
+            max_retry_after_secs: 30,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retrying a request that got the given response, `None` if it shouldn't be retried.
+    /// Rate limited (429) and unsent requests are always retried since the server never handled them, server and network
+    /// errors only if the request is safe to send twice
+    pub fn retry_delay(&self, response: &Result<Response, reqwest::Error>, attempt: u32, retry_safe: bool) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        match response {
+            Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => match retry_after(response) {
+                Some(wait) if wait <= Duration::from_secs(self.max_retry_after_secs) => Some(wait),
+                Some(_) => None,
+                None => Some(self.backoff(attempt)),
+            },
+            Ok(response) if response.status().is_server_error() && retry_safe => Some(self.backoff(attempt)),
+            Ok(_) => None,
+            Err(err) if err.is_connect() || retry_safe => Some(self.backoff(attempt)),
+            Err(_) => None,
+        }
+    }
+
+    /// Exponential backoff with jitter, so clients that failed together don't all retry together
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self.base_delay_ms.saturating_mul(2u64.saturating_pow(attempt)).min(self.max_delay_ms);
+        Duration::from_millis(rand::thread_rng().gen_range(delay / 2..=delay))
+    }
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    let secs = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(secs))
+}
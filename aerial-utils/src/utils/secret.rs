@@ -0,0 +1,78 @@
+//! Config values that shouldn't be written in the config file itself, like client secrets
+
+use std::{
+    env, fs, io,
+    path::PathBuf,
+    process::{Command, ExitStatus},
+};
+use thiserror::Error;
+
+const ENV_PREFIX: &str = "env:";
+const FILE_PREFIX: &str = "file:";
+const CMD_PREFIX: &str = "cmd:";
+
+#[derive(Debug, Error)]
+pub enum SecretError {
+    #[error("The environment variable `{0}` isn't set")]
+    MissingEnvVar(String),
+    #[error("Failed to read `{0}`: {1}")]
+    FailedToReadFile(PathBuf, io::Error),
+    #[error("Failed to run `{0}`: {1}")]
+    FailedToRunCommand(String, io::Error),
+    #[error("`{0}` failed ({1}): {2}")]
+    CommandFailed(String, ExitStatus, String),
+    #[error("Got an empty value from `{0}`")]
+    EmptyValue(String),
+}
+
+/// Gets the value a secret config value points to:
+/// - `env:VAR` is the value of the environment variable `VAR`
+/// - `file:/path` is the contents of the file
+/// - `cmd:command` is the output of the command, run by the shell (e.g. `cmd:pass show spotify/client-secret`)
+///
+/// Anything else is the value itself. Trailing whitespace is removed, since files and command outputs usually end with a newline
+pub fn resolve_secret(value: &str) -> Result<String, SecretError> {
+    let (source, secret) = if let Some(var) = value.strip_prefix(ENV_PREFIX) {
+        (value, env::var(var).map_err(|_| SecretError::MissingEnvVar(var.into()))?)
+    } else if let Some(path) = value.strip_prefix(FILE_PREFIX) {
+        (
+            value,
+            fs::read_to_string(path).map_err(|err| SecretError::FailedToReadFile(path.into(), err))?,
+        )
+    } else if let Some(command) = value.strip_prefix(CMD_PREFIX) {
+        (value, run_command(command.trim())?)
+    } else {
+        return Ok(value.into());
+    };
+
+    let secret = secret.trim_end();
+    if secret.is_empty() {
+        return Err(SecretError::EmptyValue(source.into()));
+    }
+    Ok(secret.into())
+}
+
+fn run_command(command: &str) -> Result<String, SecretError> {
+    let output = shell_command(command)
+        .output()
+        .map_err(|err| SecretError::FailedToRunCommand(command.into(), err))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(SecretError::CommandFailed(command.into(), output.status, stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into())
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
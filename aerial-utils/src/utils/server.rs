@@ -1,23 +1,36 @@
 use reqwest::Url;
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::io;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::net::TcpListener;
 use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
 
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+const STREAM_READ_TIMEOUT: Duration = Duration::from_secs(5);
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, thiserror::Error)]
 pub enum TcpServerError {
     #[error("Failed to create TCP listener: {0}")]
     FailedToCreateTcpListener(io::Error),
+    #[error("No ports were given to listen on")]
+    NoPortsGiven,
     #[error("Failed to get a stream for a request: {0}")]
     FailedToGetStream(io::Error),
     #[error("Failed parse the request made to the server: {0}")]
     FailedToParseRequest(RequestFromStringError),
     #[error("Failed to write to tab buffer")]
     FailedBufferWrite(io::Error),
+    #[error("Timed out after {0:?} without a request to a known path")]
+    TimedOut(Duration),
 }
 
+#[allow(dead_code)]
 pub struct Request {
     pub request_type: String,
     pub path: String,
@@ -34,6 +47,16 @@ pub enum RequestFromStringError {
     InvalidPath(String),
 }
 
+impl From<&Url> for Request {
+    fn from(url: &Url) -> Self {
+        Self {
+            request_type: "GET".into(),
+            path: url.path().into(),
+            params: url.query_pairs().into_owned().collect(),
+        }
+    }
+}
+
 impl TryFrom<Vec<String>> for Request {
     type Error = RequestFromStringError;
 
@@ -50,34 +73,141 @@ impl TryFrom<Vec<String>> for Request {
 
         Ok(Self {
             request_type: request_type.into(),
-            path: url.path().into(),
-            params: url.query_pairs().into_owned().collect(),
+            ..Self::from(&url)
         })
     }
 }
 
-pub fn read_localhost_request(port: u32) -> Result<Request, TcpServerError> {
-    // TODO: Actually support path
-    // TODO: Actually handle overtime
-    let listener = TcpListener::bind(format!("localhost:{}", port)).map_err(TcpServerError::FailedToCreateTcpListener)?;
-    let (stream, _) = listener.accept().map_err(TcpServerError::FailedToGetStream)?;
-    handle_requset_stream(stream)
+type RouteHandler<T, E> = Box<dyn Fn(&Request) -> Option<Result<T, E>> + Send>;
+
+/// A local HTTP server for OAuth style callbacks, it answers requests until one of its routes is hit and returns that route's result.
+/// The page shown in the browser reflects the result, so `E` should be readable by the user.
+/// A route returns `None` for requests that aren't the awaited one (like a callback for another login), the server keeps waiting then
+pub struct CallbackServer<T, E> {
+    listener: TcpListener,
+    port: u16,
+    timeout: Duration,
+    routes: HashMap<String, RouteHandler<T, E>>,
 }
 
-fn handle_requset_stream(mut stream: TcpStream) -> Result<Request, TcpServerError> {
-    let request: Vec<_> = BufReader::new(&mut stream)
+impl<T, E: Display> CallbackServer<T, E> {
+    /// Listens on the first of the given ports that is free
+    pub fn bind(ports: &[u16]) -> Result<Self, TcpServerError> {
+        let mut last_err = TcpServerError::NoPortsGiven;
+        for &port in ports {
+            match TcpListener::bind(format!("localhost:{}", port)) {
+                Ok(listener) => {
+                    return Ok(Self {
+                        listener,
+                        port,
+                        timeout: DEFAULT_TIMEOUT,
+                        routes: HashMap::new(),
+                    })
+                }
+                Err(err) => last_err = TcpServerError::FailedToCreateTcpListener(err),
+            }
+        }
+        Err(last_err)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The overall time to wait for a request to one of the routes
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn route(mut self, path: &str, handler: impl Fn(&Request) -> Option<Result<T, E>> + Send + 'static) -> Self {
+        self.routes.insert(path.into(), Box::new(handler));
+        self
+    }
+
+    /// Answers requests until a route accepts one, requests to other paths (like a favicon fetch) get a 404 and are ignored
+    pub fn serve(self) -> Result<Result<T, E>, TcpServerError> {
+        let deadline = Instant::now() + self.timeout;
+        self.listener.set_nonblocking(true).map_err(TcpServerError::FailedToGetStream)?;
+        loop {
+            let mut stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        return Err(TcpServerError::TimedOut(self.timeout));
+                    }
+                    thread::sleep(ACCEPT_POLL_INTERVAL);
+                    continue;
+                }
+                Err(err) => return Err(TcpServerError::FailedToGetStream(err)),
+            };
+
+            let request = match read_request_stream(&mut stream) {
+                Ok(request) => request,
+                // Connections browsers open in advance and then close without a request
+                Err(TcpServerError::FailedToParseRequest(RequestFromStringError::StringIsEmpty)) => continue,
+                Err(err) => {
+                    eprintln!("WARNING: Ignoring a request to the local server: {}", err);
+                    continue;
+                }
+            };
+            let Some(handler) = self.routes.get(&request.path) else {
+                write_response(stream, "404 Not Found", "").unwrap_or_else(|err| eprintln!("WARNING: {}", err));
+                continue;
+            };
+
+            let Some(result) = handler(&request) else {
+                let page = html_page("Unexpected request", "This isn't the request the server is waiting for");
+                write_response(stream, "400 Bad Request", &page).unwrap_or_else(|err| eprintln!("WARNING: {}", err));
+                continue;
+            };
+            let page = match &result {
+                Ok(_) => html_page("Done", "You can close this tab now"),
+                Err(err) => html_page("Something went wrong", &err.to_string()),
+            };
+            write_response(stream, "200 OK", &page).unwrap_or_else(|err| eprintln!("WARNING: {}", err));
+            return Ok(result);
+        }
+    }
+}
+
+fn read_request_stream(stream: &mut TcpStream) -> Result<Request, TcpServerError> {
+    // Browsers sometimes open connections they never send on, so a stream must not block the server forever
+    stream.set_nonblocking(false).map_err(TcpServerError::FailedToGetStream)?;
+    stream
+        .set_read_timeout(Some(STREAM_READ_TIMEOUT))
+        .map_err(TcpServerError::FailedToGetStream)?;
+    let request: Vec<_> = BufReader::new(stream)
         .lines()
         .map(|result| result.unwrap_or("".into()))
         .take_while(|line| !line.is_empty())
         .collect();
 
-    write_close_tab_msg(stream).unwrap_or_else(|err| println!("WARNING: {}", err));
     Request::try_from(request).map_err(TcpServerError::FailedToParseRequest)
 }
 
-fn write_close_tab_msg(mut stream: TcpStream) -> Result<(), TcpServerError> {
-    let content = "<h1>You can close this tab now</h1>";
-    let status_line = "HTTP/1.1 200 OK";
-    let response = format!("{}\r\nContent-Length: {}\r\n\r\n{}", status_line, content.len(), content);
+fn write_response(mut stream: TcpStream, status: &str, content: &str) -> Result<(), TcpServerError> {
+    let status_line = format!("HTTP/1.1 {}", status);
+    let response = format!(
+        "{}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status_line,
+        content.len(),
+        content
+    );
     stream.write_all(response.as_bytes()).map_err(TcpServerError::FailedBufferWrite)
 }
+
+fn html_page(title: &str, message: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><title>Aerial - {0}</title></head><body><h1>{0}</h1><p>{1}</p></body></html>",
+        escape_html(title),
+        escape_html(message)
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
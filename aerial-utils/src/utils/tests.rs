@@ -0,0 +1,361 @@
+use super::{
+    api_spec::RequestError,
+    cache::SpotifyCache,
+    config::SpotifyConfig,
+    config::{ConfigError, DEFAULT_PROFILE},
+    oauth::random_string,
+    paging::PageLink,
+    paths::{PathSource, CACHE_FILE, CONFIG_FILE},
+    secret::{resolve_secret, SecretError},
+    ApiRequestSpec, Cache, Config,
+};
+use crate::modules::music::Token;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// An empty directory of its own for each test, so tests don't see each other's files
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("aerial-test-{}", random_string(12)));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn env_of(vars: &[(&str, &Path)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars.iter().map(|(key, value)| (key.to_string(), value.display().to_string())).collect();
+    move |key| vars.get(key).cloned()
+}
+
+fn touch(path: &Path) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, "").unwrap();
+}
+
+#[test]
+fn flag_path_comes_first() {
+    let dir = temp_dir();
+    let env = env_of(&[("AERIAL_CONFIG", &dir.join("env.toml")), ("HOME", &dir)]);
+    let resolved = CONFIG_FILE.resolve_in(Some(dir.join("flag.toml")), env, &dir);
+    assert_eq!((resolved.path, resolved.source), (dir.join("flag.toml"), PathSource::Flag));
+}
+
+#[test]
+fn env_path_comes_before_the_xdg_directory() {
+    let dir = temp_dir();
+    touch(&dir.join("xdg/aerial/config.toml"));
+    let env = env_of(&[("AERIAL_CONFIG", &dir.join("env.toml")), ("XDG_CONFIG_HOME", &dir.join("xdg"))]);
+    let resolved = CONFIG_FILE.resolve_in(None, env, &dir);
+    assert_eq!((resolved.path, resolved.source), (dir.join("env.toml"), PathSource::Env));
+}
+
+#[test]
+fn xdg_file_comes_before_the_legacy_file() {
+    let dir = temp_dir();
+    touch(&dir.join("xdg/aerial/cache.toml"));
+    touch(&dir.join("cache.toml"));
+    let resolved = CACHE_FILE.resolve_in(None, env_of(&[("XDG_CACHE_HOME", &dir.join("xdg"))]), &dir);
+    assert_eq!((resolved.path, resolved.source), (dir.join("xdg/aerial/cache.toml"), PathSource::Xdg));
+    assert!(resolved.exists);
+}
+
+#[test]
+fn legacy_file_is_used_if_there_is_no_xdg_file() {
+    let dir = temp_dir();
+    touch(&dir.join("config.toml"));
+    let resolved = CONFIG_FILE.resolve_in(None, env_of(&[("HOME", &dir)]), &dir);
+    assert_eq!((resolved.path, resolved.source), (dir.join("config.toml"), PathSource::Legacy));
+}
+
+#[test]
+fn xdg_path_is_used_when_neither_file_exists() {
+    let dir = temp_dir();
+    let resolved = CACHE_FILE.resolve_in(None, env_of(&[("HOME", &dir)]), &dir);
+    assert_eq!((resolved.path, resolved.source), (dir.join(".cache/aerial/cache.toml"), PathSource::Xdg));
+    assert!(!resolved.exists);
+}
+
+#[test]
+fn relative_xdg_directory_is_ignored() {
+    let dir = temp_dir();
+    let env = env_of(&[("XDG_CONFIG_HOME", Path::new("relative")), ("HOME", &dir)]);
+    let resolved = CONFIG_FILE.resolve_in(None, env, &dir);
+    assert_eq!(resolved.path, dir.join(".config/aerial/config.toml"));
+}
+
+#[test]
+fn cache_directory_is_created_on_write() {
+    let path = temp_dir().join("state/aerial/cache.toml");
+    cache_with_token("token").to_file(&path).unwrap();
+    assert!(path.exists());
+}
+
+#[test]
+fn unchanged_cache_writes_no_files() {
+    let dir = temp_dir();
+    Cache::from_file(&dir.join("cache.toml"))
+        .unwrap()
+        .to_file(&dir.join("cache.toml"))
+        .unwrap();
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+}
+
+#[test]
+fn literal_secret_is_kept() {
+    assert_eq!(resolve_secret("plain-value").unwrap(), "plain-value");
+}
+
+#[test]
+fn secret_is_read_from_env() {
+    std::env::set_var("AERIAL_TEST_SECRET_FROM_ENV", "from-env");
+    assert_eq!(resolve_secret("env:AERIAL_TEST_SECRET_FROM_ENV").unwrap(), "from-env");
+    assert!(matches!(
+        resolve_secret("env:AERIAL_TEST_UNSET_SECRET"),
+        Err(SecretError::MissingEnvVar(_))
+    ));
+}
+
+#[test]
+fn secret_is_read_from_file_without_its_newline() {
+    let path = temp_dir().join("secret");
+    fs::write(&path, "from-file\n").unwrap();
+    assert_eq!(resolve_secret(&format!("file:{}", path.display())).unwrap(), "from-file");
+    let missing = format!("file:{}", path.with_file_name("missing").display());
+    assert!(matches!(resolve_secret(&missing), Err(SecretError::FailedToReadFile(..))));
+}
+
+#[cfg(unix)]
+#[test]
+fn secret_is_read_from_command_output() {
+    assert_eq!(resolve_secret("cmd:echo from-command").unwrap(), "from-command");
+    assert!(matches!(resolve_secret("cmd:echo oops >&2; exit 3"), Err(SecretError::CommandFailed(_, _, stderr)) if stderr == "oops"));
+    assert!(matches!(resolve_secret("cmd:true"), Err(SecretError::EmptyValue(_))));
+}
+
+fn spotify_config(raw_config: &str) -> SpotifyConfig {
+    let dir = temp_dir();
+    fs::write(dir.join("config.toml"), format!("[modules.spotify]\n{}", raw_config)).unwrap();
+    Config::from_file(&dir.join("config.toml")).unwrap().modules.section().unwrap().unwrap()
+}
+
+#[test]
+fn config_secrets_are_resolved_for_the_profile() {
+    let dir = temp_dir();
+    fs::write(dir.join("secret"), "file-secret\n").unwrap();
+    let spotify = spotify_config(&format!(
+        "client_id = \"id\"\nclient_secret = \"file:{}\"\n",
+        dir.join("secret").display()
+    ));
+    let spotify = spotify.with_profile(None).unwrap().unwrap();
+    assert_eq!(
+        (spotify.client_id.as_str(), spotify.client_secret.as_deref()),
+        ("id", Some("file-secret"))
+    );
+}
+
+#[test]
+fn unresolvable_config_secret_names_its_field() {
+    let spotify = spotify_config("client_id = \"env:AERIAL_TEST_UNSET_CLIENT_ID\"\n");
+    let result = spotify.with_profile(None);
+    assert!(matches!(result, Err(ConfigError::FailedToResolveSecret(field, _)) if field == "modules.spotify.client_id"));
+}
+
+#[test]
+fn secrets_of_unused_profiles_are_not_resolved() {
+    let spotify = spotify_config(
+        "client_id = \"id\"\n[modules.spotify.profiles.work]\nclient_id = \"work-id\"\nclient_secret = \"env:AERIAL_TEST_UNSET_WORK_SECRET\"\n",
+    );
+    assert_eq!(spotify.with_profile(None).unwrap().unwrap().client_id, "id");
+    let result = spotify.with_profile(Some("work"));
+    assert!(matches!(result, Err(ConfigError::FailedToResolveSecret(field, _)) if field == "modules.spotify.profiles.work.client_secret"));
+}
+
+#[test]
+fn broken_command_secret_of_an_unused_profile_does_not_fail_loading() {
+    let dir = temp_dir();
+    let raw_config = "[modules.spotify]\nclient_id = \"id\"\n[modules.spotify.profiles.work]\nclient_secret = \"cmd:exit 3\"\n";
+    fs::write(dir.join("config.toml"), raw_config).unwrap();
+    let spotify: SpotifyConfig = Config::from_file(&dir.join("config.toml")).unwrap().modules.section().unwrap().unwrap();
+    assert!(spotify.with_profile(None).is_ok());
+    assert!(matches!(
+        spotify.with_profile(Some("work")),
+        Err(ConfigError::FailedToResolveSecret(field, SecretError::CommandFailed(..))) if field == "modules.spotify.profiles.work.client_secret"
+    ));
+}
+
+fn token(access_token: &str) -> Token {
+    Token {
+        access_token: access_token.into(),
+        token_type: "Bearer".into(),
+        expires_in: Duration::from_secs(3600),
+        time_set: SystemTime::UNIX_EPOCH,
+        refresh_token: "refresh".into(),
+    }
+}
+
+fn spotify_cache_with_token(access_token: &str) -> SpotifyCache {
+    let mut spotify = SpotifyCache::default();
+    spotify.set_token(DEFAULT_PROFILE, Some(token(access_token)));
+    spotify
+}
+
+fn cache_with_token(access_token: &str) -> Cache {
+    let mut cache = Cache::default();
+    cache.modules.set_section(&spotify_cache_with_token(access_token)).unwrap();
+    cache
+}
+
+fn cached_token_of(path: &Path, profile: &str) -> Option<String> {
+    let spotify: SpotifyCache = Cache::from_file(path).unwrap().modules.section().unwrap();
+    spotify.get_token(profile).map(|token| token.access_token.clone())
+}
+
+fn cached_access_token(path: &Path) -> Option<String> {
+    cached_token_of(path, DEFAULT_PROFILE)
+}
+
+#[test]
+fn unchanged_cache_doesnt_overwrite_newer_file() {
+    let path = temp_dir().join("cache.toml");
+    cache_with_token("old").to_file(&path).unwrap();
+    let loaded = Cache::from_file(&path).unwrap();
+    // Another command refreshes the token in the meantime
+    cache_with_token("refreshed").to_file(&path).unwrap();
+    loaded.to_file(&path).unwrap();
+    assert_eq!(cached_access_token(&path).as_deref(), Some("refreshed"));
+}
+
+#[test]
+fn changed_cache_section_is_merged_into_file() {
+    let path = temp_dir().join("cache.toml");
+    fs::write(&path, "[modules.other]\nkept = true\n").unwrap();
+    let mut cache = Cache::from_file(&path).unwrap();
+    cache.modules.set_section(&spotify_cache_with_token("new")).unwrap();
+    cache.to_file(&path).unwrap();
+    assert_eq!(cached_access_token(&path).as_deref(), Some("new"));
+    assert!(fs::read_to_string(&path).unwrap().contains("[modules.other]"));
+
+    let mut cache = Cache::from_file(&path).unwrap();
+    cache.modules.set_section(&SpotifyCache::default()).unwrap();
+    cache.to_file(&path).unwrap();
+    assert_eq!(cached_access_token(&path), None);
+}
+
+#[cfg(unix)]
+#[test]
+fn cache_file_is_only_readable_by_owner() {
+    use std::os::unix::fs::PermissionsExt;
+    let path = temp_dir().join("cache.toml");
+    fs::write(&path, "").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+    cache_with_token("token").to_file(&path).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+    // A cache that is only read is made private as well
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+    Cache::from_file(&path).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+}
+
+#[test]
+fn concurrent_cache_writes_leave_a_whole_file() {
+    let path = temp_dir().join("cache.toml");
+    let handles: Vec<_> = (0..8)
+        .map(|i| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let mut cache = Cache::from_file(&path).unwrap();
+                cache.modules.set_section(&spotify_cache_with_token(&format!("token-{}", i))).unwrap();
+                cache.to_file(&path).unwrap();
+            })
+        })
+        .collect();
+    handles.into_iter().for_each(|handle| handle.join().unwrap());
+    // Every write replaced the whole file, so it's always one of them in full
+    assert!(cached_access_token(&path).unwrap().starts_with("token-"));
+}
+
+#[test]
+fn profiles_changed_at_the_same_time_are_both_kept() {
+    let path = temp_dir().join("cache.toml");
+    cache_with_token("default").to_file(&path).unwrap();
+    let mut work = Cache::from_file(&path).unwrap();
+    let mut home = Cache::from_file(&path).unwrap();
+    for (cache, profile) in [(&mut work, "work"), (&mut home, "home")] {
+        let mut spotify: SpotifyCache = cache.modules.section().unwrap();
+        spotify.set_token(profile, Some(token(profile)));
+        cache.modules.set_section(&spotify).unwrap();
+    }
+    work.to_file(&path).unwrap();
+    home.to_file(&path).unwrap();
+    let tokens: Vec<_> = ["default", "work", "home"]
+        .iter()
+        .map(|profile| cached_token_of(&path, profile))
+        .collect();
+    assert_eq!(tokens, [Some("default".into()), Some("work".into()), Some("home".into())]);
+}
+
+#[test]
+fn legacy_cache_token_belongs_to_default_profile() {
+    let path = temp_dir().join("cache.toml");
+    let legacy = r#"
+[modules.spotify.token]
+access_token = "legacy"
+token_type = "Bearer"
+refresh_token = "refresh"
+expires_in = { secs = 3600, nanos = 0 }
+time_set = { secs_since_epoch = 0, nanos_since_epoch = 0 }
+"#;
+    fs::write(&path, legacy).unwrap();
+    assert_eq!(cached_access_token(&path).as_deref(), Some("legacy"));
+}
+
+#[test]
+fn profile_credentials_fall_back_to_the_top_level_ones() {
+    let config: SpotifyConfig = toml::from_str(
+        "client_id = \"app\"\nclient_secret = \"secret\"\ndefault_profile = \"work\"\n[profiles.work]\n[profiles.other]\nclient_id = \"other-app\"",
+    )
+    .unwrap();
+    assert_eq!(config.profile_names(), ["default", "other", "work"]);
+
+    let work = config.with_profile(None).unwrap().unwrap();
+    assert_eq!(
+        (work.profile.as_str(), work.client_id.as_str(), work.client_secret.as_deref()),
+        ("work", "app", Some("secret"))
+    );
+    assert_eq!(config.with_profile(Some("default")).unwrap().unwrap().profile, "default");
+    assert!(config.with_profile(Some("missing")).unwrap().is_none());
+}
+
+#[test]
+fn profile_of_another_app_does_not_get_the_top_level_secret() {
+    let config: SpotifyConfig = toml::from_str(
+        "client_id = \"app\"\nclient_secret = \"secret\"\n[profiles.pkce]\nclient_id = \"pkce-app\"\n[profiles.own]\nclient_id = \"own-app\"\nclient_secret = \"own-secret\"",
+    )
+    .unwrap();
+    let pkce = config.with_profile(Some("pkce")).unwrap().unwrap();
+    assert_eq!((pkce.client_id.as_str(), pkce.client_secret), ("pkce-app", None));
+    let own = config.with_profile(Some("own")).unwrap().unwrap();
+    assert_eq!((own.client_id.as_str(), own.client_secret.as_deref()), ("own-app", Some("own-secret")));
+}
+
+fn build_link(url: &str) -> Result<reqwest::blocking::RequestBuilder, RequestError> {
+    PageLink::<()>::new(url.into()).build(&reqwest::blocking::Client::new(), "https://api.example.com/v1")
+}
+
+#[test]
+fn links_are_only_followed_within_the_api() {
+    let request = build_link("https://api.example.com/v1/me/top/tracks?offset=20").unwrap().build().unwrap();
+    assert_eq!(request.url().as_str(), "https://api.example.com/v1/me/top/tracks?offset=20");
+    for url in [
+        "https://evil.example.com/v1/me/top/tracks",
+        "https://api.example.com.evil/v1",
+        "https://api.example.com/v10/me",
+    ] {
+        assert!(matches!(build_link(url), Err(RequestError::ForeignUrl(..))), "{} was followed", url);
+    }
+    let invalid = PageLink::<()>::new("https://localhost:99999/v1/me".into()).build(&reqwest::blocking::Client::new(), "https://localhost:99999/v1");
+    assert!(matches!(invalid, Err(RequestError::InvalidUrl(..))));
+}